@@ -0,0 +1,544 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use crate::spells::Command;
+use crate::tile::Alteration;
+
+const ENERGY: usize = 4; // e
+const RESULT: usize = 17; // r
+const X: usize = 23;
+const Y: usize = 24;
+
+// Limits on how much of a spell the cost estimate explores before giving up on an upper bound
+const MAX_BRANCHES: usize = 200; // undecided comparisons on one path
+const MAX_STEPS: usize = 20000; // commands run in total
+
+// Registers whose value is known at compile time
+type Known = [Option<i32>; 26];
+
+// The energy a spell can spend when it succeeds
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CostEstimate {
+    pub min: Option<u32>, // None if the spell can never succeed
+    pub max: Option<u32>, // None if there is no upper bound, like a loop or an amount from an argument
+}
+
+impl fmt::Display for CostEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.min, self.max) {
+            (None, _) => write!(f, "nothing, it always fails"),
+            (Some(min), None) => write!(f, "{}+", min),
+            (Some(min), Some(max)) if min == max => write!(f, "{}", min),
+            (Some(min), Some(max)) => write!(f, "{}-{}", min, max),
+        }
+    }
+}
+
+pub fn registers_read(cmd: &Command) -> Vec<usize> {
+    match cmd {
+        Command::PushReg(reg) | Command::Poke(reg, _) | Command::MoveCursor(reg) | Command::Damage(reg) | Command::Move(reg)
+            | Command::Drop(reg) | Command::UseItem(reg) | Command::Copy(reg, _) | Command::Not(reg, _) => vec!(*reg),
+        Command::Add(a, b, _) | Command::Mul(a, b, _) | Command::Sub(a, b, _) | Command::Div(a, b, _)
+            | Command::Mod(a, b, _) | Command::And(a, b, _) | Command::Or(a, b, _) | Command::Xor(a, b, _)
+            | Command::JumpIfGt(a, b, _) | Command::DamageRadius(a, b) | Command::QueryValidLocation(a, b)
+            | Command::QueryPassableLocation(a, b) | Command::QueryMonsterLocation(a, b) | Command::QueryTileKind(a, b) => vec!(*a, *b),
+        Command::DamageLine(a, b, c) | Command::DamageCone(a, b, c) => vec!(*a, *b, *c),
+        _ => Vec::new(),
+    }
+}
+
+pub fn registers_written(cmd: &Command) -> Vec<usize> {
+    match cmd {
+        Command::Pop(reg) | Command::Peek(_, reg) | Command::Copy(_, reg) | Command::Store(_, reg) | Command::Not(_, reg) => vec!(*reg),
+        Command::Add(_, _, dest) | Command::Mul(_, _, dest) | Command::Sub(_, _, dest) | Command::Div(_, _, dest)
+            | Command::Mod(_, _, dest) | Command::And(_, _, dest) | Command::Or(_, _, dest) | Command::Xor(_, _, dest) => vec!(*dest),
+        Command::QueryEnergy => vec!(ENERGY),
+        Command::QueryLocationSelf | Command::QueryLocationCursor | Command::PromptDirection | Command::PromptLocation => vec!(X, Y),
+        Command::QueryValidLocation(_, _) | Command::QueryPassableLocation(_, _) | Command::QueryMonsterLocation(_, _)
+            | Command::QueryTileKind(_, _) => vec!(RESULT),
+        _ => Vec::new(),
+    }
+}
+
+// The value an operator stores, when its operands are known and it can't overflow
+fn evaluate(cmd: &Command, known: &Known) -> Option<i32> {
+    let binary = |a: &usize, b: &usize, op: fn(i32, i32) -> Option<i32>| match (known[*a], known[*b]) {
+        (Some(a), Some(b)) => op(a, b),
+        _ => None,
+    };
+    match cmd {
+        Command::Store(val, _) => Some(*val),
+        Command::Copy(src, _) => known[*src],
+        Command::Add(a, b, _) => binary(a, b, |a, b| a.checked_add(b)),
+        Command::Mul(a, b, _) => binary(a, b, |a, b| a.checked_mul(b)),
+        Command::Sub(a, b, _) => binary(a, b, |a, b| a.checked_sub(b)),
+        Command::Div(a, b, _) => binary(a, b, |a, b| a.checked_div(b)),
+        Command::Mod(a, b, _) => binary(a, b, |a, b| a.checked_rem(b)),
+        Command::And(a, b, _) => binary(a, b, |a, b| Some(a & b)),
+        Command::Or(a, b, _) => binary(a, b, |a, b| Some(a | b)),
+        Command::Xor(a, b, _) => binary(a, b, |a, b| Some(a ^ b)),
+        Command::Not(a, _) => known[*a].map(|a| !a),
+        _ => None,
+    }
+}
+
+fn step_known(cmd: &Command, known: &mut Known) {
+    let value = evaluate(cmd, known);
+    for reg in registers_written(cmd) {
+        known[reg] = value;
+    }
+}
+
+fn is_operator(cmd: &Command) -> bool {
+    match cmd {
+        Command::Add(..) | Command::Mul(..) | Command::Sub(..) | Command::Div(..) | Command::Mod(..)
+            | Command::And(..) | Command::Or(..) | Command::Xor(..) | Command::Not(..) => true,
+        _ => false,
+    }
+}
+
+// Replaces operators on known values with stores, pushes of known registers with pushed values
+// and decided comparisons with a jump or a noop. Values are only tracked within straight line
+// code, anything can be in a register at a jump target or after a call.
+pub fn fold_constants(commands: &mut [Command], entries: &[usize]) {
+    let targets = jump_targets(commands, entries);
+    let mut known: Known = [None; 26];
+    for (address, cmd) in commands.iter_mut().enumerate() {
+        if targets.contains(&address) {
+            known = [None; 26];
+        }
+        let folded = match cmd {
+            Command::PushReg(reg) => known[*reg].map(Command::PushVal),
+            Command::JumpIfGt(a, b, dest) => match (known[*a], known[*b]) {
+                (Some(a), Some(b)) if a > b => Some(Command::Jump(*dest)),
+                (Some(_), Some(_)) => Some(Command::Noop),
+                _ => None,
+            },
+            _ if is_operator(cmd) => evaluate(cmd, &known).map(|val| Command::Store(val, registers_written(cmd)[0])),
+            _ => None,
+        };
+        if let Some(folded) = folded {
+            *cmd = folded;
+        }
+        step_known(cmd, &mut known);
+        match cmd {
+            Command::Call(_) | Command::Jump(_) | Command::Return => known = [None; 26],
+            _ => (),
+        }
+    }
+}
+
+// Divisions and remainders whose divisor is known to be zero, so they always fail. Values are
+// tracked like in `fold_constants`.
+pub fn zero_divisors(commands: &[Command], entries: &[usize]) -> Vec<usize> {
+    let targets = jump_targets(commands, entries);
+    let mut known: Known = [None; 26];
+    let mut found = Vec::new();
+    for (address, cmd) in commands.iter().enumerate() {
+        if targets.contains(&address) {
+            known = [None; 26];
+        }
+        match cmd {
+            Command::Div(_, b, _) | Command::Mod(_, b, _) if known[*b] == Some(0) => found.push(address),
+            _ => (),
+        }
+        step_known(cmd, &mut known);
+        match cmd {
+            Command::Call(_) | Command::Jump(_) | Command::Return => known = [None; 26],
+            _ => (),
+        }
+    }
+    found
+}
+
+// Where anything can be in a register, since execution can arrive from elsewhere
+fn jump_targets(commands: &[Command], entries: &[usize]) -> HashSet<usize> {
+    let mut targets: HashSet<usize> = entries.iter().cloned().collect();
+    for cmd in commands.iter() {
+        match cmd {
+            Command::Call(dest) | Command::Jump(dest) | Command::JumpIfGt(_, _, dest) => { targets.insert(*dest); },
+            _ => (),
+        }
+    }
+    targets
+}
+
+// Where execution can go after the command at `address`
+fn successors(commands: &[Command], address: usize) -> Vec<usize> {
+    match commands[address] {
+        Command::Jump(dest) => vec!(dest),
+        Command::JumpIfGt(_, _, dest) | Command::Call(dest) => vec!(dest, address + 1),
+        Command::Return => Vec::new(),
+        _ => vec!(address + 1),
+    }
+}
+
+// Addresses that can run when starting from any of `entries`
+pub fn reachable(commands: &[Command], entries: &[usize]) -> Vec<bool> {
+    let mut seen = vec!(false; commands.len());
+    let mut todo: Vec<usize> = entries.to_vec();
+    while let Some(address) = todo.pop() {
+        if address < commands.len() && !seen[address] {
+            seen[address] = true;
+            todo.extend(successors(commands, address));
+        }
+    }
+    seen
+}
+
+// The commands worth keeping: reachable, not noops, and not jumps to where execution would go anyway
+pub fn live(commands: &[Command], entries: &[usize]) -> Vec<bool> {
+    let reachable = reachable(commands, entries);
+    let mut keep: Vec<bool> = commands.iter().zip(reachable)
+        .map(|(cmd, reachable)| reachable && match cmd { Command::Noop => false, _ => true })
+        .collect();
+    // The first kept address at or after each address
+    let mut next_kept = vec!(commands.len(); commands.len() + 1);
+    for address in (0..commands.len()).rev() {
+        next_kept[address] = if keep[address] { address } else { next_kept[address + 1] };
+    }
+    for (address, cmd) in commands.iter().enumerate() {
+        if let Command::Jump(dest) = cmd {
+            if keep[address] && *dest <= commands.len() && next_kept[*dest] == next_kept[address + 1] {
+                keep[address] = false;
+            }
+        }
+    }
+    keep
+}
+
+// Registers a spell reads without any command it can run writing them first. They still hold
+// whatever the caster passed in, so this is only a problem for spells that take no arguments.
+pub fn unwritten_reads(commands: &[Command], entry: usize) -> Vec<(usize, usize)> {
+    let reachable = reachable(commands, &[entry]);
+    let runs = || commands.iter().enumerate().filter(|(address, _)| reachable[*address]);
+    let written: HashSet<usize> = runs().flat_map(|(_, cmd)| registers_written(cmd)).collect();
+    let mut reported = HashSet::new();
+    let mut reads = Vec::new();
+    for (address, cmd) in runs() {
+        for reg in registers_read(cmd) {
+            if !written.contains(&reg) && reported.insert(reg) {
+                reads.push((address, reg));
+            }
+        }
+    }
+    reads
+}
+
+// The energy one command costs if it succeeds, at least and at most
+fn command_cost(cmd: &Command, known: &Known) -> (u32, Option<u32>) {
+    let positive = |reg: &usize| known[*reg].map(|val| val.max(0) as u32);
+    let area = |energy: &usize, tiles: Option<u32>| match positive(energy) {
+        Some(energy) => (energy, tiles.and_then(|tiles| energy.checked_mul(tiles))),
+        None => (0, None),
+    };
+    // Depends on the tile at the cursor
+    let alteration = |alteration: Alteration| {
+        let (min, max) = alteration.cost_range();
+        (min, Some(max))
+    };
+    match cmd {
+        Command::MoveCursor(_) => (5, Some(5)),
+        // Or opens a door in the way
+        Command::Move(_) => {
+            let (min, max) = Alteration::Open.cost_range();
+            (min.min(10), Some(max.max(10)))
+        },
+        Command::OpenDoor => alteration(Alteration::Open),
+        Command::CloseDoor => alteration(Alteration::Close),
+        Command::Dig => alteration(Alteration::Dig),
+        Command::BuildWall => alteration(Alteration::BuildWall),
+        Command::Ignite => alteration(Alteration::Ignite),
+        Command::Damage(reg) => match positive(reg) {
+            Some(energy) => (energy, Some(energy)),
+            None => (0, None),
+        },
+        Command::DamageRadius(energy, radius) => area(energy, positive(radius).and_then(|r| (2 * r + 1).checked_mul(2 * r + 1))),
+        Command::DamageLine(energy, _, length) => area(energy, positive(length)),
+        // Every row of the cone is at most two tiles wider than the last
+        Command::DamageCone(energy, _, length) => area(energy, positive(length).and_then(|l| l.checked_mul(l + 2))),
+        _ => (0, Some(0)),
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct State {
+    address: usize,
+    calls: Vec<usize>, // return addresses
+    stack: Vec<Option<i32>>,
+    known: Known,
+}
+
+// The cheapest and dearest way to finish from a state
+#[derive(Clone, Copy)]
+struct Rest {
+    min: Option<u32>, // None if no path from here finishes
+    max: u32,
+}
+
+const FAILS: Rest = Rest { min: None, max: 0 };
+
+struct Explorer<'a> {
+    commands: &'a [Command],
+    finished: HashMap<State, Rest>,
+    on_path: HashMap<State, u32>, // energy spent when the state was entered
+    steps: usize,
+    underflows: BTreeSet<usize>,
+    unbounded: bool,
+}
+
+impl<'a> Explorer<'a> {
+    // Runs straight line code in place and only recurses where a comparison can go either way
+    fn visit(&mut self, state: State, spent: u32) -> Rest {
+        if let Some(rest) = self.finished.get(&state) {
+            return *rest
+        }
+        if let Some(before) = self.on_path.get(&state) {
+            // A loop that costs energy can go round as often as the caster can pay for
+            if spent > *before {
+                self.unbounded = true;
+            }
+            return FAILS
+        }
+        if self.on_path.len() >= MAX_BRANCHES {
+            self.unbounded = true;
+            return FAILS
+        }
+        self.on_path.insert(state.clone(), spent);
+        let mut current = state.clone();
+        let mut seen: HashMap<State, u32> = HashMap::new();
+        let (mut min_cost, mut max_cost) = (0u32, 0u32);
+        let rest = loop {
+            self.steps += 1;
+            if self.steps > MAX_STEPS {
+                self.unbounded = true;
+                break FAILS
+            }
+            // Going round without a comparison that could stop it never finishes
+            if let Some(before) = seen.insert(current.clone(), min_cost) {
+                if min_cost > before {
+                    self.unbounded = true;
+                }
+                break FAILS
+            }
+            let cmd = match self.commands.get(current.address) {
+                Some(cmd) => cmd,
+                None => break FAILS,
+            };
+            let (min, max) = command_cost(cmd, &current.known);
+            if max.is_none() {
+                self.unbounded = true;
+            }
+            min_cost = min_cost.saturating_add(min);
+            max_cost = max_cost.saturating_add(max.unwrap_or(min));
+            match self.next_states(&current, cmd) {
+                Ok(Some(mut states)) => if states.len() == 1 {
+                    current = states.pop().unwrap();
+                } else {
+                    let spent = spent.saturating_add(min_cost);
+                    break states.into_iter().fold(FAILS, |rest, next| {
+                        let next = self.visit(next, spent);
+                        match (rest.min, next.min) {
+                            (None, _) => next,
+                            (_, None) => rest,
+                            (Some(a), Some(b)) => Rest { min: Some(a.min(b)), max: rest.max.max(next.max) },
+                        }
+                    })
+                },
+                Ok(None) => break Rest { min: Some(0), max: 0 },
+                Err(()) => break FAILS,
+            }
+        };
+        self.on_path.remove(&state);
+        let rest = Rest {
+            min: rest.min.map(|min| min.saturating_add(min_cost)),
+            max: rest.max.saturating_add(max_cost),
+        };
+        self.finished.insert(state, rest);
+        rest
+    }
+
+    // The states after running `cmd`, None when the spell finishes and an error when it fails
+    fn next_states(&mut self, state: &State, cmd: &Command) -> Result<Option<Vec<State>>, ()> {
+        let mut next = state.clone();
+        next.address += 1;
+        step_known(cmd, &mut next.known);
+        match cmd {
+            Command::PushVal(val) => next.stack.push(Some(*val)),
+            Command::PushReg(reg) => next.stack.push(state.known[*reg]),
+            Command::Pop(reg) => match next.stack.pop() {
+                Some(val) => next.known[*reg] = val,
+                None => {
+                    self.underflows.insert(state.address);
+                    return Err(())
+                },
+            },
+            Command::Peek(depth, reg) => match next.stack.len().checked_sub(depth + 1) {
+                Some(index) => next.known[*reg] = next.stack[index],
+                None => {
+                    self.underflows.insert(state.address);
+                    return Err(())
+                },
+            },
+            Command::Poke(reg, depth) => match next.stack.len().checked_sub(depth + 1) {
+                Some(index) => next.stack[index] = state.known[*reg],
+                None => {
+                    self.underflows.insert(state.address);
+                    return Err(())
+                },
+            },
+            Command::Call(dest) => {
+                next.calls.push(next.address);
+                next.address = *dest;
+            },
+            Command::Return => match next.calls.pop() {
+                Some(address) => next.address = address,
+                None => return Ok(None),
+            },
+            Command::Jump(dest) => next.address = *dest,
+            Command::JumpIfGt(a, b, dest) => match (state.known[*a], state.known[*b]) {
+                (Some(a), Some(b)) if a > b => next.address = *dest,
+                (Some(_), Some(_)) => (),
+                _ => {
+                    let mut taken = next.clone();
+                    taken.address = *dest;
+                    return Ok(Some(vec!(taken, next)))
+                },
+            },
+            Command::PromptDirection | Command::PromptLocation => return Err(()),
+            _ => (),
+        }
+        Ok(Some(vec!(next)))
+    }
+}
+
+// What a spell can cost, and the addresses where it can run out of stack. Nothing is known
+// about the registers when the spell starts, since they hold its arguments.
+pub fn estimate_cost(commands: &[Command], entry: usize) -> (CostEstimate, Vec<usize>) {
+    let mut explorer = Explorer {
+        commands: commands,
+        finished: HashMap::new(),
+        on_path: HashMap::new(),
+        steps: 0,
+        underflows: BTreeSet::new(),
+        unbounded: false,
+    };
+    let start = State {
+        address: entry,
+        calls: Vec::new(),
+        stack: Vec::new(),
+        known: [None; 26],
+    };
+    let rest = explorer.visit(start, 0);
+    let estimate = CostEstimate {
+        min: rest.min,
+        max: if explorer.unbounded || rest.min.is_none() { None } else { Some(rest.max) },
+    };
+    (estimate, explorer.underflows.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spells::Command::*;
+    use crate::spells::Spellbook;
+    use super::{fold_constants, live, estimate_cost, unwritten_reads, zero_divisors, CostEstimate};
+
+    fn estimate(min: Option<u32>, max: Option<u32>) -> CostEstimate {
+        CostEstimate { min: min, max: max }
+    }
+
+    #[test]
+    fn folds_known_values_within_straight_line_code() {
+        let mut commands = vec!(
+            Store(2, 0),
+            Store(3, 1),
+            Add(0, 1, 2),
+            PushReg(2),
+            JumpIfGt(2, 0, 7),
+            Mul(2, 3, 4), // never runs
+            Return,
+            Add(2, 0, 5), // a jump target, so nothing is known here
+            Return,
+        );
+        fold_constants(&mut commands, &[0]);
+        assert_eq!(commands, vec!(
+            Store(2, 0),
+            Store(3, 1),
+            Store(5, 2),
+            PushVal(5),
+            Jump(7),
+            Mul(2, 3, 4),
+            Return,
+            Add(2, 0, 5),
+            Return,
+        ));
+    }
+
+    #[test]
+    fn leaves_failing_arithmetic_to_run_time() {
+        // Overflow and division by zero fail the spell when it runs, so they aren't folded
+        let mut commands = vec!(Store(i32::MAX, 0), Store(0, 1), Add(0, 0, 2), Div(0, 1, 3), Return);
+        fold_constants(&mut commands, &[0]);
+        assert_eq!(commands[2..], [Add(0, 0, 2), Div(0, 1, 3), Return]);
+    }
+
+    #[test]
+    fn divisors_known_to_be_zero() {
+        let commands = [Store(0, 1), Div(0, 1, 2), Mod(0, 1, 2), Div(1, 0, 2), Return, Div(0, 1, 2), Return];
+        assert_eq!(zero_divisors(&commands, &[0, 5]), vec!(1, 2));
+        let spellbook = Spellbook::compile(":halve: store 8 a; store 0 b; div a b c; move c; return;").unwrap();
+        assert_eq!(spellbook.warnings().len(), 1);
+        assert!(spellbook.warnings()[0].1.contains("division by zero"));
+    }
+
+    #[test]
+    fn drops_noops_unreachable_code_and_pointless_jumps() {
+        let commands = vec!(Noop, Jump(2), Noop, Move(0), Return, Move(1), Return);
+        assert_eq!(live(&commands, &[0]), vec!(false, false, false, true, true, false, false));
+    }
+
+    #[test]
+    fn costs_of_straight_line_and_branching_spells() {
+        assert_eq!(estimate_cost(&[MoveCursor(0), MoveCursor(0), Return], 0), (estimate(Some(10), Some(10)), Vec::new()));
+        // Moving into a closed door opens it instead
+        assert_eq!(estimate_cost(&[Move(0), Return], 0).0, estimate(Some(2), Some(10)));
+        // Either moves the cursor once or twice
+        let branch = [JumpIfGt(0, 1, 3), MoveCursor(0), MoveCursor(0), MoveCursor(0), Return];
+        assert_eq!(estimate_cost(&branch, 0).0, estimate(Some(5), Some(15)));
+        // The damage comes from an argument, and an area can be cut down to one tile by the edge
+        // of the level
+        assert_eq!(estimate_cost(&[Damage(0), Return], 0).0, estimate(Some(0), None));
+        assert_eq!(estimate_cost(&[Store(7, 0), Store(2, 1), DamageRadius(0, 1), Return], 0).0, estimate(Some(7), Some(175)));
+    }
+
+    #[test]
+    fn loops_and_failures() {
+        // Moves until the argument runs out
+        let loop_ = [Store(0, 1), JumpIfGt(1, 0, 5), Move(0), Sub(0, 2, 0), Jump(1), Return];
+        assert_eq!(estimate_cost(&loop_, 0).0, estimate(Some(0), None));
+        assert_eq!(estimate_cost(&[Move(0), PromptDirection, Return], 0).0, estimate(None, None));
+        assert_eq!(estimate_cost(&[PushVal(1), Pop(0), Pop(1), Return], 0), (estimate(None, None), vec!(2)));
+        assert_eq!(estimate_cost(&[PushVal(1), Peek(1, 0), Return], 0), (estimate(None, None), vec!(1)));
+        assert_eq!(estimate_cost(&[PushVal(1), Store(3, 0), Poke(0, 0), Pop(1), Return], 0).1, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn calls_add_the_callee_cost() {
+        let commands = [Call(3), Call(3), Return, MoveCursor(0), Return];
+        assert_eq!(estimate_cost(&commands, 0).0, estimate(Some(10), Some(10)));
+    }
+
+    #[test]
+    fn reads_of_unset_registers() {
+        let commands = [Store(1, 0), Add(0, 1, 2), Move(2), Move(3), Return];
+        assert_eq!(unwritten_reads(&commands, 0), vec!((1, 1), (3, 3)));
+    }
+
+    #[test]
+    fn spellbooks_are_optimized_before_their_costs_are_estimated() {
+        let spellbook = Spellbook::compile(":aim: store 4 a; store 2 b; jump_if_gt b a skip; move_cursor a; skip: move_cursor a; return;").unwrap();
+        assert_eq!(spellbook.cost("aim"), Some(estimate(Some(10), Some(10))));
+        // The comparison is decided, so it's gone
+        assert!(spellbook.span(4).is_some() && spellbook.span(5).is_none());
+    }
+}
@@ -0,0 +1,166 @@
+use std::f32::consts::PI;
+
+use crate::events::GameEvent;
+use crate::utils::AbsoluteLocation;
+
+// Lengths in update frames, which run 60 times a second
+const STEP_FRAMES: u32 = 8; // per tile walked
+const LUNGE_FRAMES: u32 = 12;
+const POPUP_FRAMES: u32 = 45;
+const FADE_FRAMES: u32 = 30;
+const LUNGE_DISTANCE: f32 = 0.3; // how far into the target's tile an attacker leans
+const POPUP_RISE: f32 = 0.8; // tiles a damage number floats up
+
+// Positions are in tiles, fractions included
+pub type Position = (f32, f32);
+
+enum Motion {
+    Walk(Vec<AbsoluteLocation>), // every tile passed through, ending where the monster stands now
+    Lunge(AbsoluteLocation, AbsoluteLocation), // attacker, target
+    Popup(AbsoluteLocation, String),
+    Fade(AbsoluteLocation, &'static str), // where a monster died, its sprite
+}
+
+struct Tween {
+    motion: Motion,
+    delay: u32, // frames before it starts
+    frame: u32,
+    frames: u32,
+}
+
+impl Tween {
+    fn new(motion: Motion, delay: u32, frames: u32) -> Tween {
+        Tween {
+            motion: motion,
+            delay: delay,
+            frame: 0,
+            frames: frames,
+        }
+    }
+
+    // From 0 at the start to 1 once it's done
+    fn progress(&self) -> f32 {
+        self.frame.saturating_sub(self.delay) as f32 / self.frames as f32
+    }
+
+    fn is_done(&self) -> bool {
+        self.frame >= self.delay + self.frames
+    }
+}
+
+// Tweens played after each turn, driven by the turn's events. The level has already changed
+// by the time they play, so they only decide where things are drawn.
+pub struct Animations {
+    tweens: Vec<Tween>,
+}
+
+impl Animations {
+    pub fn new() -> Animations {
+        Animations {
+            tweens: Vec::new(),
+        }
+    }
+
+    // Input waits until this is false
+    pub fn is_busy(&self) -> bool {
+        !self.tweens.is_empty()
+    }
+
+    pub fn tick(&mut self) {
+        for tween in self.tweens.iter_mut() {
+            tween.frame += 1;
+        }
+        self.tweens.retain(|tween| !tween.is_done());
+    }
+
+    // `sprites` are the monsters' locations and sprites from before the turn, so the dead can fade out
+    pub fn start(&mut self, events: &[GameEvent], sprites: &[(AbsoluteLocation, &'static str)]) {
+        for event in events {
+            match event {
+                GameEvent::Moved(_, from, to) => {
+                    // Monsters that move several times in a turn walk the whole way in one go
+                    let walk = self.tweens.iter_mut().find(|tween| match &tween.motion {
+                        Motion::Walk(path) => path.last() == Some(from),
+                        _ => false,
+                    });
+                    match walk {
+                        Some(tween) => {
+                            if let Motion::Walk(path) = &mut tween.motion {
+                                path.push(*to);
+                            }
+                            tween.frames += STEP_FRAMES;
+                        },
+                        None => self.tweens.push(Tween::new(Motion::Walk(vec!(*from, *to)), 0, STEP_FRAMES)),
+                    }
+                },
+                GameEvent::Attacked(attacker, target) => {
+                    self.tweens.push(Tween::new(Motion::Lunge(*attacker, *target), 0, LUNGE_FRAMES));
+                },
+                // Numbers pop up as the blow lands
+                GameEvent::Damaged(_, location, damage) => {
+                    self.tweens.push(Tween::new(Motion::Popup(*location, format!("-{}", damage)), LUNGE_FRAMES / 2, POPUP_FRAMES));
+                },
+                GameEvent::Died(_, location) => {
+                    let start = self.walked_from(location);
+                    if let Some((_, sprite)) = sprites.iter().find(|(loc, _)| *loc == start) {
+                        self.tweens.push(Tween::new(Motion::Fade(*location, *sprite), LUNGE_FRAMES / 2, FADE_FRAMES));
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+
+    // Where a monster that is now at `location` stood before the turn
+    fn walked_from(&self, location: &AbsoluteLocation) -> AbsoluteLocation {
+        self.tweens.iter().filter_map(|tween| match &tween.motion {
+            Motion::Walk(path) if path.last() == Some(location) => path.first().cloned(),
+            _ => None,
+        }).next().unwrap_or(*location)
+    }
+
+    // Where to draw the monster standing at `location`
+    pub fn position(&self, location: &AbsoluteLocation) -> Position {
+        let mut position = (location.0 as f32, location.1 as f32);
+        for tween in self.tweens.iter() {
+            match &tween.motion {
+                Motion::Walk(path) if path.last() == Some(location) => {
+                    let along = tween.progress() * (path.len() - 1) as f32;
+                    let step = (along as usize).min(path.len() - 2);
+                    let (from, to) = (path[step], path[step + 1]);
+                    let t = along - step as f32;
+                    position = (
+                        from.0 as f32 + (to.0 as f32 - from.0 as f32) * t,
+                        from.1 as f32 + (to.1 as f32 - from.1 as f32) * t,
+                    );
+                },
+                Motion::Lunge(attacker, target) if attacker == location => {
+                    let reach = (tween.progress() * PI).sin() * LUNGE_DISTANCE;
+                    position.0 += (target.0 as f32 - attacker.0 as f32).max(-1.0).min(1.0) * reach;
+                    position.1 += (target.1 as f32 - attacker.1 as f32).max(-1.0).min(1.0) * reach;
+                },
+                _ => (),
+            }
+        }
+        position
+    }
+
+    // Damage numbers with where to draw them and how opaque they are
+    pub fn popups(&self) -> Vec<(Position, &str, f32)> {
+        self.tweens.iter().filter(|tween| tween.frame >= tween.delay).filter_map(|tween| match &tween.motion {
+            Motion::Popup(location, text) => {
+                let t = tween.progress();
+                Some(((location.0 as f32, location.1 as f32 - POPUP_RISE * t), text.as_str(), 1.0 - t))
+            },
+            _ => None,
+        }).collect()
+    }
+
+    // Sprites of monsters that just died, with how opaque they still are
+    pub fn fades(&self) -> Vec<(AbsoluteLocation, &'static str, f32)> {
+        self.tweens.iter().filter_map(|tween| match &tween.motion {
+            Motion::Fade(location, sprite) => Some((*location, *sprite, 1.0 - tween.progress())),
+            _ => None,
+        }).collect()
+    }
+}
@@ -0,0 +1,124 @@
+// Command line tool for checking spellbooks and trying spells outside the game
+extern crate arlogue;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use arlogue::level::Level;
+use arlogue::journal::RollbackPolicy;
+use arlogue::spells::{CasterRef, CompileError, Spellbook, SpellEngine};
+use arlogue::events::GameEvent;
+
+const USAGE: &'static str = "usage:
+    arlogue-spell check <spellbook>
+    arlogue-spell analyze <spellbook>
+    arlogue-spell disassemble <spellbook>
+    arlogue-spell run [--keep-effects] <spellbook> <level> <spell> [args...]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.get(0).map(|command| command.as_str()) {
+        Some("check") if args.len() == 2 => compile(&args[1]).and_then(|spellbook| {
+            let mut spells = spellbook.spell_names();
+            spells.sort();
+            print_warnings(&args[1], &spellbook)?;
+            println!("{}: ok, {} spells: {}", args[1], spells.len(), spells.join(", "));
+            Ok(())
+        }),
+        Some("analyze") if args.len() == 2 => compile(&args[1]).and_then(|spellbook| {
+            let mut spells = spellbook.spell_names();
+            spells.sort();
+            for spell in spells {
+                if let Some(cost) = spellbook.cost(&spell) {
+                    println!("{:20} {}", spell, cost);
+                }
+            }
+            print_warnings(&args[1], &spellbook)
+        }),
+        Some("disassemble") if args.len() == 2 => compile(&args[1]).and_then(|spellbook| {
+            for line in spellbook.disassemble(&read(&args[1])?) {
+                println!("{}", line);
+            }
+            Ok(())
+        }),
+        Some("run") if args.len() >= 5 && args[1] == "--keep-effects" => run(&args[2], &args[3], &args[4], &args[5..], RollbackPolicy::KeepEffects),
+        Some("run") if args.len() >= 4 => run(&args[1], &args[2], &args[3], &args[4..], RollbackPolicy::UndoFailed),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))
+}
+
+// Includes are looked up next to the spellbook; `.script` files use the higher level language
+fn compile(path: &str) -> Result<Spellbook, String> {
+    let source = read(path)?;
+    let dir = Path::new(path).parent().map(|dir| dir.to_path_buf()).unwrap_or_default();
+    let include = move |name: &str| read(&dir.join(name).to_string_lossy());
+    let spellbook = if path.ends_with(".script") {
+        Spellbook::compile_script(&source)
+    } else {
+        Spellbook::compile_with(&source, Box::new(include))
+    };
+    spellbook.map_err(|err| match err.line_col(&source) {
+        Some((line, col)) => format!("{}:{}:{}: {}", path, line + 1, col + 1, err.message),
+        None => format!("{}: {}", path, err.message),
+    })
+}
+
+fn print_warnings(path: &str, spellbook: &Spellbook) -> Result<(), String> {
+    let source = read(path)?;
+    for ((start, _), message) in spellbook.warnings() {
+        let warning = CompileError { offset: Some(start), message: message };
+        let (line, col) = warning.line_col(&source).unwrap_or((0, 0));
+        println!("{}:{}:{}: warning: {}", path, line + 1, col + 1, warning.message);
+    }
+    Ok(())
+}
+
+// Casts the spell as the player for one turn, then prints the level
+fn run(spellbook_path: &str, level_path: &str, spell: &str, args: &[String], rollback: RollbackPolicy) -> Result<(), String> {
+    let spellbook = compile(spellbook_path)?;
+    let level = Level::parse(&read(level_path)?).map_err(|err| format!("{}: {}", level_path, err))?;
+    let args = args.iter().map(|arg| arg.parse::<i32>().map_err(|_| format!("argument '{}' is not a number", arg)))
+        .collect::<Result<Vec<i32>, String>>()?;
+    let mut engine = SpellEngine::new(level);
+    engine.rollback = rollback;
+    let events = engine.do_turn_with_args(spell, &args, &spellbook, &Spellbook::monster_spellbook());
+
+    println!("turn {}", engine.turn);
+    for effect in engine.journal.effects() {
+        println!("effect {}", effect);
+    }
+    for effect in engine.journal.undone() {
+        println!("undone {}", effect);
+    }
+    let mut error = None;
+    for event in events {
+        println!("event {:?}", event);
+        match event {
+            GameEvent::SpellFailed(CasterRef::Player, _, message) | GameEvent::SpellFailed(CasterRef::Item(0, _), _, message) => error = Some(message),
+            _ => (),
+        }
+    }
+    for line in engine.level.to_text() {
+        println!("{}", line);
+    }
+    for monster in engine.level.monsters.iter() {
+        let (col, row) = monster.location();
+        println!("{} at ({}, {}): hp {}/{}, energy {}/{}, {} items",
+            monster.mtype.name(), col, row, monster.stats.current_hp, monster.stats.max_hp,
+            monster.caster.energy, monster.caster.max_energy(), monster.inventory.len());
+    }
+    match error {
+        Some(error) => Err(format!("{} failed: {}", spell, error)),
+        None => Ok(()),
+    }
+}
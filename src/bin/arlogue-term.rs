@@ -0,0 +1,20 @@
+// Terminal frontend, for playing over SSH or without a GPU
+extern crate arlogue;
+
+use std::process;
+
+// usage: arlogue-term [level file]
+#[cfg(unix)]
+fn main() {
+    let level_path = std::env::args().nth(1).unwrap_or(arlogue::terminal::LEVEL_FILE.to_string());
+    if let Err(err) = arlogue::terminal::run(&level_path) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("the terminal frontend needs a unix terminal");
+    process::exit(1);
+}
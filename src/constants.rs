@@ -1,52 +1,93 @@
+// Spells inscribed in the player's spellbook at the start of a game
+pub const STARTING_SPELLS: &[&str] = &[
+    "left", "right", "up", "down", "up_left", "up_right", "down_left", "down_right", "wait",
+    "attack_left", "attack_right", "attack_up", "attack_down",
+    "attack_up_left", "attack_up_right", "attack_down_left", "attack_down_right",
+    "pick_up", "drop", "use_item",
+];
+
+pub const ANT_LORE: &[&str] = &[
+    "nova",
+];
+
 pub const MONSTER_SPELLBOOK: &'static str = "noop;
-:left:
-store #left d;
-move d;
-return;
 
-:right:
-store #right d;
-move d;
-return;
+const ATTACK_ENERGY = 5;
 
-:up:
-store #up d;
+macro step(dir) {
+:$dir:
+store #$dir d;
 move d;
 return;
+}
 
-:down:
-store #down d;
-move d;
+macro attack(dir) {
+:attack_$dir:
+store #$dir d;
+move_cursor d;
+store #ATTACK_ENERGY a;
+damage a;
 return;
+}
+
+step!(left);
+step!(right);
+step!(up);
+step!(down);
+step!(up_left);
+step!(up_right);
+step!(down_left);
+step!(down_right);
 
 :wait: return;
 
-:attack_left:
-store #left d;
-move_cursor d;
-store 5 a;
-damage a;
+attack!(left);
+attack!(right);
+attack!(up);
+attack!(down);
+attack!(up_left);
+attack!(up_right);
+attack!(down_left);
+attack!(down_right);
+
+:pick_up:
+pickup;
 return;
 
-:attack_right:
-store #right d;
-move_cursor d;
-store 5 a;
-damage a;
+:drop:
+drop a;
 return;
 
-:attack_up:
-store #up d;
-move_cursor d;
-store 5 a;
-damage a;
+:use_item:
+use_item a;
 return;
 
-:attack_down:
-store #down d;
-move_cursor d;
-store 5 a;
-damage a;
+:nova:
+store 1 a;
+store 1 r;
+damage_radius a r;
+return;
+
+";
+
+// Item programs see a copy of the user's registers when they start
+pub const WAND_OF_FIRE: &'static str = ":zap:
+store 2 a;
+store 2 r;
+damage_radius a r;
 return;
+";
 
+pub const SCROLL_OF_LIGHTNING: &'static str = ":zap:
+store 2 a;
+store 6 l;
+store #left d;
+damage_line a d l;
+store #right d;
+damage_line a d l;
+store #up d;
+damage_line a d l;
+store #down d;
+damage_line a d l;
+return;
 ";
@@ -0,0 +1,138 @@
+use quicksilver::input::Key;
+
+// A small multi-line text editor for writing spells in game
+pub struct SpellEditor {
+    pub lines: Vec<String>,
+    pub row: usize,
+    pub col: usize,
+    pub error: Option<(Option<(usize, usize)>, String)>, // line and column, message
+    pub binding: bool, // waiting for a key to bind the current spell to
+}
+
+impl SpellEditor {
+    pub fn new(source: &str) -> SpellEditor {
+        let mut lines: Vec<String> = source.lines().map(|line| line.to_string()).collect();
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        SpellEditor {
+            lines: lines,
+            row: 0,
+            col: 0,
+            error: None,
+            binding: false,
+        }
+    }
+
+    pub fn source(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    // Returns true if the text changed
+    pub fn handle_key(&mut self, key: Key, shift: bool) -> bool {
+        match key {
+            Key::Left => if self.col > 0 {
+                self.col -= 1;
+            } else if self.row > 0 {
+                self.row -= 1;
+                self.col = self.lines[self.row].len();
+            },
+            Key::Right => if self.col < self.lines[self.row].len() {
+                self.col += 1;
+            } else if self.row + 1 < self.lines.len() {
+                self.row += 1;
+                self.col = 0;
+            },
+            Key::Up => if self.row > 0 {
+                self.row -= 1;
+                self.col = self.col.min(self.lines[self.row].len());
+            },
+            Key::Down => if self.row + 1 < self.lines.len() {
+                self.row += 1;
+                self.col = self.col.min(self.lines[self.row].len());
+            },
+            Key::Home => self.col = 0,
+            Key::End => self.col = self.lines[self.row].len(),
+            Key::Return => {
+                let rest = self.lines[self.row].split_off(self.col);
+                self.row += 1;
+                self.col = 0;
+                self.lines.insert(self.row, rest);
+                return true;
+            },
+            Key::Tab => {
+                self.lines[self.row].insert_str(self.col, "    ");
+                self.col += 4;
+                return true;
+            },
+            Key::Back => if self.col > 0 {
+                self.col -= 1;
+                self.lines[self.row].remove(self.col);
+                return true;
+            } else if self.row > 0 {
+                let line = self.lines.remove(self.row);
+                self.row -= 1;
+                self.col = self.lines[self.row].len();
+                self.lines[self.row].push_str(&line);
+                return true;
+            },
+            Key::Delete => if self.col < self.lines[self.row].len() {
+                self.lines[self.row].remove(self.col);
+                return true;
+            } else if self.row + 1 < self.lines.len() {
+                let line = self.lines.remove(self.row + 1);
+                self.lines[self.row].push_str(&line);
+                return true;
+            },
+            _ => if let Some(c) = key_char(key, shift) {
+                self.lines[self.row].insert(self.col, c);
+                self.col += 1;
+                return true;
+            },
+        }
+        false
+    }
+
+    // The `:label:` spell the cursor is in, if any
+    pub fn current_spell(&self) -> Option<String> {
+        self.lines[..=self.row].iter().rev().filter_map(|line| {
+            let line = line.trim();
+            if line.starts_with(':') {
+                line[1..].split(':').next().map(|label| label.trim().to_string())
+            } else {
+                None
+            }
+        }).find(|label| !label.is_empty())
+    }
+}
+
+// The character typed by a key on a US layout; only what the spell language needs
+fn key_char(key: Key, shift: bool) -> Option<char> {
+    let letters = [
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    ];
+    let digits = [Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+    let shifted_digits = [')', '!', '@', '#', '$', '%', '^', '&', '*', '('];
+    if let Some(i) = letters.iter().position(|k| *k == key) {
+        let c = (b'a' + i as u8) as char;
+        return Some(if shift { c.to_ascii_uppercase() } else { c })
+    }
+    if let Some(i) = digits.iter().position(|k| *k == key) {
+        return Some(if shift { shifted_digits[i] } else { (b'0' + i as u8) as char })
+    }
+    match (key, shift) {
+        (Key::Space, _) => Some(' '),
+        (Key::Semicolon, false) => Some(';'),
+        (Key::Semicolon, true) | (Key::Colon, _) => Some(':'),
+        (Key::Minus, false) | (Key::Subtract, _) => Some('-'),
+        (Key::Minus, true) | (Key::Underline, _) => Some('_'),
+        (Key::Period, false) => Some('.'),
+        (Key::Comma, false) => Some(','),
+        (Key::Slash, false) => Some('/'),
+        (Key::Equals, false) => Some('='),
+        (Key::Equals, true) => Some('+'),
+        (Key::Apostrophe, true) => Some('"'),
+        _ => None,
+    }
+}
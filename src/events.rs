@@ -0,0 +1,21 @@
+use crate::utils::AbsoluteLocation;
+use crate::spells::CasterRef;
+use crate::monster::MonsterType;
+
+// Everything that happened during a turn, in order, returned by `SpellEngine::do_turn`
+#[derive(PartialEq, Debug)]
+pub enum GameEvent {
+    Moved(MonsterType, AbsoluteLocation, AbsoluteLocation), // who, from, to
+    Attacked(AbsoluteLocation, AbsoluteLocation), // attacker, target
+    Damaged(MonsterType, AbsoluteLocation, u32), // who, location, damage
+    Died(MonsterType, AbsoluteLocation), // who, location
+    SpellFailed(CasterRef, String, String), // caster, spell, error
+    EnergySpent(CasterRef, u32),
+    Blast(Vec<AbsoluteLocation>), // every tile covered by an area spell
+    PickedUp(String), // item name
+    Dropped(String), // item name
+    ItemUsed(String), // description of what happened
+    SpellRevealed(String, String), // spell label, where it was learned from
+    HazardHit(MonsterType, AbsoluteLocation, String), // who, location, what hit them
+    TerrainChanged(AbsoluteLocation),
+}
@@ -0,0 +1,134 @@
+use crate::level::Level;
+use crate::monster::MonsterType;
+use crate::spells::{SpellEngine, Spellbook, CasterRef};
+use crate::events::GameEvent;
+use crate::messages::{MessageLog, MessageCategory};
+use crate::learning::SpellMemory;
+use constants::STARTING_SPELLS;
+
+// Everything about a game that doesn't depend on how it's drawn, shared by the frontends
+pub struct Game {
+    pub spell_engine: SpellEngine,
+    pub player_spellbook: Spellbook, // the monster spellbook plus the player's own spells
+    pub monster_spellbook: Spellbook,
+    pub spell_memory: SpellMemory,
+    pub log: MessageLog,
+}
+
+impl Game {
+    pub fn new(level: Level) -> Game {
+        Game {
+            spell_engine: SpellEngine::new(level),
+            player_spellbook: Spellbook::monster_spellbook(),
+            monster_spellbook: Spellbook::monster_spellbook(),
+            spell_memory: SpellMemory::starting(STARTING_SPELLS),
+            log: MessageLog::new(),
+        }
+    }
+
+    // Swaps in a new level, keeping everything the player has learned
+    pub fn enter_level(&mut self, level: Level, source: &str) {
+        self.spell_engine.level = level;
+        self.log.push(self.spell_engine.turn, MessageCategory::System, format!("Loaded {}", source));
+    }
+
+    pub fn cycle_rollback(&mut self) {
+        let rollback = self.spell_engine.rollback.next();
+        self.spell_engine.rollback = rollback;
+        self.log.push(self.spell_engine.turn, MessageCategory::System, rollback.describe().to_string());
+    }
+
+    // Letters pick spells from the spellbook
+    pub fn toggle_inscribed(&mut self, index: usize) {
+        if let Some(spell) = self.spell_memory.toggle_inscribed(index) {
+            let verb = if spell.inscribed { "inscribe" } else { "erase" };
+            let text = format!("You {} {}", verb, spell.label);
+            self.log.push(self.spell_engine.turn, MessageCategory::System, text);
+        }
+    }
+
+    pub fn forget(&mut self, index: usize) {
+        if let Some(spell) = self.spell_memory.forget(index) {
+            self.log.push(self.spell_engine.turn, MessageCategory::System, format!("You forget {}", spell.label));
+        }
+    }
+
+    // Casts the spell as the player's turn and logs what happened. Returns the turn's events and
+    // false if the player's spell failed; spells that can't be cast at all don't take a turn.
+    pub fn run_turn(&mut self, spell: &str, args: &[i32]) -> (Vec<GameEvent>, bool) {
+        // The game is over until another level or a save is loaded
+        if self.spell_engine.level.monsters[0].is_dead() {
+            self.log.push(self.spell_engine.turn, MessageCategory::System, "You are dead".to_string());
+            return (Vec::new(), false);
+        }
+        // Key bindings can name spells that were never written
+        if !self.player_spellbook.has_spell(spell) {
+            let text = format!("There is no spell called {}", spell);
+            self.log.push(self.spell_engine.turn, MessageCategory::SpellError, text);
+            return (Vec::new(), false);
+        }
+        if !self.spell_memory.can_cast(spell) {
+            let text = format!("{} is not inscribed in your spellbook", spell);
+            self.log.push(self.spell_engine.turn, MessageCategory::SpellError, text);
+            return (Vec::new(), false);
+        }
+        // Spells that can't possibly be paid for don't take a turn
+        let energy = self.spell_engine.level.monsters[0].caster.energy;
+        if let Some(cost) = self.player_spellbook.cost(spell) {
+            match (cost.min, cost.max) {
+                (Some(min), _) if min > energy => {
+                    let text = format!("{} needs at least {} energy, you have {}", spell, min, energy);
+                    self.log.push(self.spell_engine.turn, MessageCategory::SpellError, text);
+                    return (Vec::new(), false);
+                },
+                (_, Some(max)) if max > energy => {
+                    let text = format!("{} can cost up to {} energy, you have {}", spell, max, energy);
+                    self.log.push(self.spell_engine.turn, MessageCategory::System, text);
+                },
+                _ => (),
+            }
+        }
+        let events = self.spell_engine.do_turn_with_args(spell, args, &self.player_spellbook, &self.monster_spellbook);
+        let turn = self.spell_engine.turn;
+        let mut succeeded = true;
+        for event in events.iter() {
+            match event {
+                GameEvent::Damaged(mtype, _, damage) => {
+                    let text = if *mtype == MonsterType::Player {
+                        format!("You take {} damage", damage)
+                    } else {
+                        format!("The {} takes {} damage", mtype.name(), damage)
+                    };
+                    self.log.push(turn, MessageCategory::Combat, text)
+                },
+                GameEvent::Died(mtype, _) => {
+                    let text = if *mtype == MonsterType::Player { "You die".to_string() } else { format!("The {} dies", mtype.name()) };
+                    self.log.push(turn, MessageCategory::Combat, text)
+                },
+                // Spells cast by the player's items fail the player's turn too
+                GameEvent::SpellFailed(CasterRef::Player, _, error) | GameEvent::SpellFailed(CasterRef::Item(0, _), _, error) => {
+                    self.log.push(turn, MessageCategory::SpellError, error.clone());
+                    succeeded = false;
+                },
+                GameEvent::HazardHit(mtype, _, tile) => {
+                    let text = if *mtype == MonsterType::Player {
+                        format!("You are caught by the {}", tile)
+                    } else {
+                        format!("The {} is caught by the {}", mtype.name(), tile)
+                    };
+                    self.log.push(turn, MessageCategory::Combat, text)
+                },
+                GameEvent::Blast(_) | GameEvent::SpellFailed(_, _, _) | GameEvent::Moved(_, _, _) | GameEvent::Attacked(_, _)
+                    | GameEvent::EnergySpent(_, _) | GameEvent::TerrainChanged(_) => (),
+                GameEvent::PickedUp(name) => self.log.push(turn, MessageCategory::Item, format!("You pick up the {}", name)),
+                GameEvent::Dropped(name) => self.log.push(turn, MessageCategory::Item, format!("You drop the {}", name)),
+                GameEvent::ItemUsed(description) => self.log.push(turn, MessageCategory::Item, description.clone()),
+                GameEvent::SpellRevealed(label, source) => if self.spell_memory.discover(label, source) {
+                    let text = format!("You discover {} (from {}); inscribe it with F4", label, source);
+                    self.log.push(turn, MessageCategory::System, text);
+                },
+            }
+        }
+        (events, succeeded)
+    }
+}
@@ -0,0 +1,134 @@
+use std::rc::Rc;
+
+use crate::spells::{Spellbook, Caster};
+use constants::{WAND_OF_FIRE, SCROLL_OF_LIGHTNING};
+
+#[derive(Clone)]
+pub enum ItemKind {
+    Weapon(u32), // extra melee damage while wielded
+    Armor(u8), // armor class bonus while worn
+    Scroll, // used up after one reading
+    Wand,
+    EnergyPotion(u32), // energy restored
+}
+
+// A spell program carried by an item, cast with the item's own energy charge
+#[derive(Clone)]
+pub struct ItemSpell {
+    pub spellbook: Rc<Spellbook>,
+    pub caster: Caster,
+}
+
+// Item programs start at this label
+pub const ITEM_SPELL_ENTRY: &'static str = "zap";
+
+#[derive(Clone)]
+pub struct Item {
+    pub name: String,
+    pub kind: ItemKind,
+    pub sprite: &'static str,
+    pub equipped: bool,
+    pub spell: Option<ItemSpell>,
+    pub teaches: Option<String>, // spell label revealed by reading this
+}
+
+impl Item {
+    fn new(name: &str, kind: ItemKind, sprite: &'static str) -> Item {
+        Item {
+            name: name.to_string(),
+            kind: kind,
+            sprite: sprite,
+            equipped: false,
+            spell: None,
+            teaches: None,
+        }
+    }
+
+    // Wands and scrolls whose program is written in the spell language
+    pub fn enchanted(name: &str, kind: ItemKind, sprite: &'static str, source: &str, charge: u32) -> Result<Item, String> {
+        let spellbook = Spellbook::compile(source).map_err(|err| err.message)?;
+        if !spellbook.spell_names().iter().any(|name| name == ITEM_SPELL_ENTRY) {
+            return Err(format!("{} has no :{}: spell", name, ITEM_SPELL_ENTRY))
+        }
+        let mut item = Item::new(name, kind, sprite);
+        item.spell = Some(ItemSpell {
+            spellbook: Rc::new(spellbook),
+            caster: Caster::charged((0, 0), charge),
+        });
+        Ok(item)
+    }
+
+    pub fn dagger() -> Item {
+        Item::new("dagger", ItemKind::Weapon(2), "dagger")
+    }
+
+    pub fn leather_armor() -> Item {
+        Item::new("leather armor", ItemKind::Armor(2), "leather_armor")
+    }
+
+    pub fn blank_scroll() -> Item {
+        Item::new("blank scroll", ItemKind::Scroll, "blank_scroll")
+    }
+
+    pub fn scroll_of_nova() -> Item {
+        let mut item = Item::new("scroll of nova", ItemKind::Scroll, "scroll_of_nova");
+        item.teaches = Some("nova".to_string());
+        item
+    }
+
+    pub fn oak_wand() -> Item {
+        Item::new("oak wand", ItemKind::Wand, "oak_wand")
+    }
+
+    pub fn wand_of_fire() -> Item {
+        Item::enchanted("wand of fire", ItemKind::Wand, "wand_of_fire", WAND_OF_FIRE, 100).expect("wand of fire should compile")
+    }
+
+    pub fn scroll_of_lightning() -> Item {
+        Item::enchanted("scroll of lightning", ItemKind::Scroll, "scroll_of_lightning", SCROLL_OF_LIGHTNING, 50).expect("scroll of lightning should compile")
+    }
+
+    pub fn energy_potion() -> Item {
+        Item::new("energy potion", ItemKind::EnergyPotion(10), "energy_potion")
+    }
+
+    // Level files name items like their sprites
+    pub fn from_name(name: &str) -> Option<Item> {
+        match name {
+            "dagger" => Some(Item::dagger()),
+            "leather_armor" => Some(Item::leather_armor()),
+            "blank_scroll" => Some(Item::blank_scroll()),
+            "scroll_of_nova" => Some(Item::scroll_of_nova()),
+            "oak_wand" => Some(Item::oak_wand()),
+            "wand_of_fire" => Some(Item::wand_of_fire()),
+            "scroll_of_lightning" => Some(Item::scroll_of_lightning()),
+            "energy_potion" => Some(Item::energy_potion()),
+            _ => None,
+        }
+    }
+
+    pub fn is_equippable(&self) -> bool {
+        match self.kind {
+            ItemKind::Weapon(_) | ItemKind::Armor(_) => true,
+            _ => false,
+        }
+    }
+
+    // Classic roguelike glyph for the terminal
+    pub fn symbol(&self) -> char {
+        match self.kind {
+            ItemKind::Weapon(_) => ')',
+            ItemKind::Armor(_) => '[',
+            ItemKind::Scroll => '?',
+            ItemKind::Wand => '/',
+            ItemKind::EnergyPotion(_) => '!',
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.spell {
+            Some(spell) => format!("{} ({} energy)", self.name, spell.caster.energy),
+            None => self.name.clone(),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+
+use crate::level::Level;
+use crate::monster::Monster;
+use crate::item::Item;
+use crate::tile::Tile;
+use crate::spells::CasterRef;
+use crate::utils::AbsoluteLocation;
+
+// What happens to the effects of a spell that fails partway through
+#[derive(Copy, Clone, PartialEq)]
+pub enum RollbackPolicy {
+    KeepEffects,
+    UndoOutOfEnergy, // only spells that ran out of energy are undone
+    UndoFailed, // all or nothing
+}
+
+impl RollbackPolicy {
+    pub fn applies(&self, out_of_energy: bool) -> bool {
+        match self {
+            RollbackPolicy::KeepEffects => false,
+            RollbackPolicy::UndoOutOfEnergy => out_of_energy,
+            RollbackPolicy::UndoFailed => true,
+        }
+    }
+
+    pub fn next(&self) -> RollbackPolicy {
+        match self {
+            RollbackPolicy::KeepEffects => RollbackPolicy::UndoOutOfEnergy,
+            RollbackPolicy::UndoOutOfEnergy => RollbackPolicy::UndoFailed,
+            RollbackPolicy::UndoFailed => RollbackPolicy::KeepEffects,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            RollbackPolicy::KeepEffects => "Failed spells leave their effects behind",
+            RollbackPolicy::UndoOutOfEnergy => "Spells that run out of energy are undone",
+            RollbackPolicy::UndoFailed => "Failed spells are undone",
+        }
+    }
+}
+
+// A change a spell made to the level, with what it takes to undo it
+pub enum Effect {
+    Spent(CasterRef, u32, u32), // caster, cost, energy before
+    Moved(usize, AbsoluteLocation, AbsoluteLocation), // monster, from, to
+    Damaged(usize, u32, u32), // monster, damage, hp before
+    Died(usize, Monster, usize), // index it had, the monster before the killing blow, items it dropped
+    Items(usize, String, Monster, Vec<(AbsoluteLocation, Item)>), // monster, what happened, the monster and the floor before
+    Terrain(AbsoluteLocation, Tile), // the tile before
+}
+
+impl Effect {
+    fn undo(self, level: &mut Level) {
+        match self {
+            Effect::Spent(caster_ref, _, energy) => level.set_energy(&caster_ref, energy),
+            Effect::Moved(index, from, _) => level.monsters[index].caster.move_to(from),
+            Effect::Damaged(index, _, hp) => level.monsters[index].stats.current_hp = hp,
+            Effect::Died(index, monster, dropped) => {
+                let items = level.items.len() - dropped;
+                level.items.truncate(items);
+                level.monsters.insert(index, monster);
+            },
+            Effect::Items(index, _, monster, floor) => {
+                level.monsters[index] = monster;
+                level.items = floor;
+            },
+            Effect::Terrain(location, tile) => {
+                level.set_tile(&location, tile);
+            },
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Effect::Spent(caster_ref, cost, energy) => write!(f, "{:?} spent {} of {} energy", caster_ref, cost, energy),
+            Effect::Moved(index, from, to) => write!(f, "monster {} moved from {:?} to {:?}", index, from, to),
+            Effect::Damaged(index, damage, hp) => write!(f, "monster {} took {} damage with {} hp", index, damage, hp),
+            Effect::Died(index, monster, dropped) => write!(f, "monster {} ({}) died and dropped {} items", index, monster.mtype.name(), dropped),
+            Effect::Items(index, description, _, _) => write!(f, "monster {}: {}", index, description),
+            Effect::Terrain(location, tile) => write!(f, "the {} at {:?} changed", tile.kind.name(), location),
+        }
+    }
+}
+
+// Effects of this turn's spells, oldest first, and what failed spells did before being undone
+pub struct Journal {
+    effects: Vec<Effect>,
+    undone: Vec<String>,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal {
+            effects: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, effect: Effect) {
+        self.effects.push(effect);
+    }
+
+    // Where a spell's effects start, for `rollback`
+    pub fn mark(&self) -> usize {
+        self.effects.len()
+    }
+
+    // Undoes everything recorded since `mark`, newest first
+    pub fn rollback(&mut self, mark: usize, level: &mut Level) {
+        while self.effects.len() > mark {
+            let effect = self.effects.pop().unwrap();
+            self.undone.push(format!("{}", effect));
+            effect.undo(level);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+        self.undone.clear();
+    }
+
+    pub fn effects(&self) -> &[Effect] {
+        &self.effects
+    }
+
+    // Newest first, in the order they were undone
+    pub fn undone(&self) -> &[String] {
+        &self.undone
+    }
+}
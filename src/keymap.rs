@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use quicksilver::input::Key;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub key: Key,
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl KeyChord {
+    pub fn parse(input: &str) -> Result<KeyChord, String> {
+        let mut chord = KeyChord {
+            key: Key::Unlabeled,
+            shift: false,
+            ctrl: false,
+            alt: false,
+        };
+        let mut key = None;
+        for part in input.split('+').map(|part| part.trim().to_lowercase()) {
+            match part.as_str() {
+                "shift" => chord.shift = true,
+                "ctrl" => chord.ctrl = true,
+                "alt" => chord.alt = true,
+                name => if key.is_some() {
+                    return Err(format!("'{}' names more than one key", input))
+                } else if let Some(k) = key_from_name(name) {
+                    key = Some(k)
+                } else {
+                    return Err(format!("unknown key '{}'", name))
+                },
+            }
+        }
+        match key {
+            Some(key) => {
+                chord.key = key;
+                Ok(chord)
+            },
+            None => Err(format!("'{}' has no key", input)),
+        }
+    }
+
+    // Function keys open screens and such, whatever else is held, and escape closes them. The
+    // terminal frontend quits on ctrl+c and ctrl+d.
+    pub fn is_reserved(&self) -> bool {
+        match self.key {
+            Key::F1 | Key::F2 | Key::F3 | Key::F4 | Key::F5 | Key::F6 | Key::F7 | Key::F8 | Key::F9 | Key::F10
+                | Key::Escape => true,
+            Key::C | Key::D => self.ctrl,
+            _ => false,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.ctrl { parts.push("ctrl"); }
+        if self.alt { parts.push("alt"); }
+        if self.shift { parts.push("shift"); }
+        parts.push(key_name(self.key));
+        parts.join("+")
+    }
+}
+
+// Each binding is a macro: the listed spells are cast one after another as separate turns
+pub struct Keymap {
+    bindings: HashMap<KeyChord, Vec<String>>,
+    order: Vec<KeyChord>, // file order, for the help screen
+}
+
+impl Keymap {
+    // Format is one `chord = spell, spell, ...` binding per line, with `#` comments. Spells are
+    // looked up when the key is pressed, since the player can write and discover more of them.
+    pub fn parse(source: &str) -> Result<Keymap, String> {
+        let mut bindings = HashMap::new();
+        let mut lines: HashMap<KeyChord, usize> = HashMap::new();
+        let mut order = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let line_num = i + 1;
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut sides = line.splitn(2, '=');
+            let chord = sides.next().unwrap();
+            let spells = match sides.next() {
+                Some(spells) => spells,
+                None => return Err(format!("line {}: expected `key = spell`", line_num)),
+            };
+            let chord = KeyChord::parse(chord).map_err(|err| format!("line {}: {}", line_num, err))?;
+            if chord.is_reserved() {
+                return Err(format!("line {}: {} is used by the game itself", line_num, chord.describe()));
+            }
+            if let Some(previous) = lines.get(&chord) {
+                return Err(format!("line {}: {} is already bound on line {}", line_num, chord.describe(), previous));
+            }
+            let spells: Vec<String> = spells.split(',').map(|spell| spell.trim().to_string()).collect();
+            for spell in spells.iter() {
+                if spell.is_empty() {
+                    return Err(format!("line {}: empty spell name", line_num));
+                }
+            }
+            lines.insert(chord, line_num);
+            bindings.insert(chord, spells);
+            order.push(chord);
+        }
+        Ok(Keymap {
+            bindings: bindings,
+            order: order,
+        })
+    }
+
+    // Returns the spells the chord was bound to before, if any
+    pub fn bind(&mut self, chord: KeyChord, spells: Vec<String>) -> Option<Vec<String>> {
+        if !self.bindings.contains_key(&chord) {
+            self.order.push(chord);
+        }
+        self.bindings.insert(chord, spells)
+    }
+
+    pub fn get(&self, chord: &KeyChord) -> Option<&Vec<String>> {
+        self.bindings.get(chord)
+    }
+
+    // `describe_spell` can add details like the spell's cost
+    pub fn describe(&self, describe_spell: &dyn Fn(&str) -> String) -> Vec<String> {
+        self.order.iter().map(|chord| {
+            let spells: Vec<String> = self.bindings[chord].iter().map(|spell| describe_spell(spell)).collect();
+            format!("{}: {}", chord.describe(), spells.join(", "))
+        }).collect()
+    }
+}
+
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("a", Key::A), ("b", Key::B), ("c", Key::C), ("d", Key::D), ("e", Key::E), ("f", Key::F),
+    ("g", Key::G), ("h", Key::H), ("i", Key::I), ("j", Key::J), ("k", Key::K), ("l", Key::L),
+    ("m", Key::M), ("n", Key::N), ("o", Key::O), ("p", Key::P), ("q", Key::Q), ("r", Key::R),
+    ("s", Key::S), ("t", Key::T), ("u", Key::U), ("v", Key::V), ("w", Key::W), ("x", Key::X),
+    ("y", Key::Y), ("z", Key::Z),
+    ("0", Key::Key0), ("1", Key::Key1), ("2", Key::Key2), ("3", Key::Key3), ("4", Key::Key4),
+    ("5", Key::Key5), ("6", Key::Key6), ("7", Key::Key7), ("8", Key::Key8), ("9", Key::Key9),
+    ("numpad0", Key::Numpad0), ("numpad1", Key::Numpad1), ("numpad2", Key::Numpad2),
+    ("numpad3", Key::Numpad3), ("numpad4", Key::Numpad4), ("numpad5", Key::Numpad5),
+    ("numpad6", Key::Numpad6), ("numpad7", Key::Numpad7), ("numpad8", Key::Numpad8),
+    ("numpad9", Key::Numpad9),
+    ("f1", Key::F1), ("f2", Key::F2), ("f3", Key::F3), ("f4", Key::F4), ("f5", Key::F5),
+    ("f6", Key::F6), ("f7", Key::F7), ("f8", Key::F8), ("f9", Key::F9), ("f10", Key::F10),
+    ("f11", Key::F11), ("f12", Key::F12),
+    ("left", Key::Left), ("right", Key::Right), ("up", Key::Up), ("down", Key::Down),
+    ("home", Key::Home), ("end", Key::End), ("pageup", Key::PageUp), ("pagedown", Key::PageDown),
+    ("insert", Key::Insert), ("delete", Key::Delete), ("backspace", Key::Back),
+    ("return", Key::Return), ("space", Key::Space), ("tab", Key::Tab), ("escape", Key::Escape),
+    ("period", Key::Period), ("comma", Key::Comma), ("slash", Key::Slash),
+    ("semicolon", Key::Semicolon), ("apostrophe", Key::Apostrophe), ("minus", Key::Minus),
+    ("equals", Key::Equals), ("lbracket", Key::LBracket), ("rbracket", Key::RBracket),
+    ("backslash", Key::Backslash), ("grave", Key::Grave),
+];
+
+// Position in the alphabet for letter keys, used to pick from lettered lists
+pub fn letter_index(key: Key) -> Option<usize> {
+    KEY_NAMES.iter().take(26).position(|(_, k)| *k == key)
+}
+
+pub fn key_from_name(name: &str) -> Option<Key> {
+    KEY_NAMES.iter().find(|(key_name, _)| *key_name == name).map(|(_, key)| *key)
+}
+
+fn key_name(key: Key) -> &'static str {
+    KEY_NAMES.iter().find(|(_, k)| *k == key).map(|(name, _)| *name).unwrap_or("?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Keymap;
+
+    #[test]
+    fn keys_the_game_uses_cannot_be_bound() {
+        assert!(Keymap::parse("f11 = nova\nctrl+f = nova, nova").is_ok());
+        assert_eq!(Keymap::parse("h = left\n\nshift+f3 = nova").err().unwrap(), "line 3: shift+f3 is used by the game itself");
+        assert_eq!(Keymap::parse("escape = wait").err().unwrap(), "line 1: escape is used by the game itself");
+        assert_eq!(Keymap::parse("ctrl+c = wait").err().unwrap(), "line 1: ctrl+c is used by the game itself");
+    }
+
+    #[test]
+    fn spells_are_only_looked_up_when_cast() {
+        assert!(Keymap::parse("f = written_later").is_ok());
+        assert!(Keymap::parse(include_str!("../static/keymap.txt")).is_ok());
+        assert_eq!(Keymap::parse("f = nova,").err().unwrap(), "line 1: empty spell name");
+    }
+}
@@ -0,0 +1,71 @@
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KnownSpell {
+    pub label: String,
+    pub source: String, // where the spell was discovered
+    pub inscribed: bool, // only inscribed spells can be cast
+}
+
+// The spells the player has discovered, in the order they were found
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SpellMemory {
+    spells: Vec<KnownSpell>,
+}
+
+impl SpellMemory {
+    pub fn starting(labels: &[&str]) -> SpellMemory {
+        SpellMemory {
+            spells: labels.iter().map(|label| KnownSpell {
+                label: label.to_string(),
+                source: "starting spellbook".to_string(),
+                inscribed: true,
+            }).collect(),
+        }
+    }
+
+    pub fn spells(&self) -> &[KnownSpell] {
+        &self.spells
+    }
+
+    pub fn can_cast(&self, label: &str) -> bool {
+        self.spells.iter().any(|spell| spell.label == label && spell.inscribed)
+    }
+
+    // Returns false if the spell was already known
+    pub fn discover(&mut self, label: &str, source: &str) -> bool {
+        if self.spells.iter().any(|spell| spell.label == label) {
+            false
+        } else {
+            self.spells.push(KnownSpell {
+                label: label.to_string(),
+                source: source.to_string(),
+                inscribed: false,
+            });
+            true
+        }
+    }
+
+    // Spells the player writes themselves are inscribed straight away
+    pub fn write(&mut self, label: &str) -> bool {
+        let new = self.discover(label, "written by you");
+        if let Some(spell) = self.spells.iter_mut().find(|spell| spell.label == label) {
+            spell.inscribed = true;
+        }
+        new
+    }
+
+    // Inscribes the spell in the given position, or erases it if it's already inscribed
+    pub fn toggle_inscribed(&mut self, index: usize) -> Option<&KnownSpell> {
+        self.spells.get_mut(index).map(|spell| {
+            spell.inscribed = !spell.inscribed;
+            &*spell
+        })
+    }
+
+    pub fn forget(&mut self, index: usize) -> Option<KnownSpell> {
+        if index < self.spells.len() {
+            Some(self.spells.remove(index))
+        } else {
+            None
+        }
+    }
+}
@@ -1,16 +1,72 @@
-use crate::tile::Tile;
-use crate::monster::{Monster, MonsterType};
-use crate::spells::{CasterRef, Caster};
-use crate::utils::{AbsoluteLocation, RelativeLocation};
+use crate::tile::{Tile, TileKind};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::item::{Item, ItemKind, ITEM_SPELL_ENTRY};
+use crate::monster::{Monster, MonsterType, Condition};
+use crate::spells::{CasterRef, Caster, Spellbook};
+use crate::utils::{AbsoluteLocation, RelativeLocation, Direction};
 
 const LEVEL_SIZE: usize = 20;
 
+// Map characters level files know without a legend line, and the names they stand for
+const DEFAULT_LEGEND: &[(char, &str)] = &[
+    (' ', "nothing"), ('.', "floor"), ('|', "wall"), ('-', "wall"), ('#', "wall"), ('+', "closed_door"),
+    ('\'', "open_door"), ('~', "water"), ('}', "lava"), ('^', "spike_trap"), ('"', "drain_trap"),
+    ('<', "stairs_up"), ('>', "stairs_down"), ('@', "player"), ('a', "ant"),
+];
+
+// A `#` starts a comment at the start of a line or after a space, so it can still sit inside
+// words. The first `keep` characters are never a comment, which leaves room for a `#` legend.
+fn strip_comment(text: &str, keep: usize) -> &str {
+    let mut after_space = true;
+    for (n, (i, c)) in text.char_indices().enumerate() {
+        if c == '#' && after_space && n >= keep {
+            return &text[..i]
+        }
+        after_space = c.is_whitespace();
+    }
+    text
+}
+
+// The terrain a map character stands for and the monsters and items on it
+fn legend_entry(names: &[&str]) -> Result<(TileKind, Vec<String>), String> {
+    let mut terrain = None;
+    let mut things = Vec::new();
+    for name in names {
+        if let Some(kind) = TileKind::from_name(name) {
+            if terrain.is_some() {
+                return Err("more than one terrain kind".to_string())
+            }
+            terrain = Some(kind);
+        } else if Monster::from_name(name, (0, 0)).is_some() || Item::from_name(name).is_some() {
+            things.push(name.to_string());
+        } else {
+            return Err(format!("unknown terrain, monster or item '{}'", name))
+        }
+    }
+    Ok((terrain.unwrap_or(TileKind::Floor), things))
+}
+
+#[derive(Clone)]
 pub struct Level {
     pub terrain: [[Tile; LEVEL_SIZE]; LEVEL_SIZE],
     pub monsters: Vec<Monster>,
+    pub items: Vec<(AbsoluteLocation, Item)>, // items lying on the floor
+    pub depth: u32,
 }
 
 impl Level {
+    // Solid rock with nobody on it
+    pub fn new(depth: u32) -> Level {
+        Level {
+            terrain: [[Tile::nothing(); LEVEL_SIZE]; LEVEL_SIZE],
+            monsters: Vec::new(),
+            items: Vec::new(),
+            depth: depth,
+        }
+    }
+
     pub fn cast(&mut self, caster_ref: &CasterRef, cost: u32) -> bool { // false if not enough energy
         self.caster_mut(caster_ref).cast(cost)
     }
@@ -19,29 +75,82 @@ impl Level {
         self.caster(caster_ref).energy
     }
 
-    pub fn move_to(&mut self, caster_ref: &CasterRef, location: AbsoluteLocation) { self.caster_mut(caster_ref).move_to(location) }
+    pub fn set_energy(&mut self, caster_ref: &CasterRef, energy: u32) {
+        self.caster_mut(caster_ref).energy = energy;
+    }
+
+    // Items move whoever is holding them
+    pub fn move_to(&mut self, caster_ref: &CasterRef, location: AbsoluteLocation) {
+        match caster_ref {
+            CasterRef::Item(holder, _) => self.monsters[*holder].caster.move_to(location),
+            _ => self.caster_mut(caster_ref).move_to(location),
+        }
+    }
 
     pub fn regen(&mut self, caster_ref: &CasterRef) {
-        self.caster_mut(caster_ref).regen()
+        if let CasterRef::Item(_, _) = caster_ref {
+            // Item charges don't regenerate
+            return;
+        }
+        let index = self.monster_index(caster_ref);
+        if !self.monsters[index].is_affected(Condition::Drained) {
+            self.caster_mut(caster_ref).regen();
+        }
+        self.monsters[index].tick_effects();
+    }
+
+    pub fn weapon_bonus(&self, caster_ref: &CasterRef) -> u32 {
+        self.monsters[self.monster_index(caster_ref)].weapon_bonus()
     }
 
     pub fn location(&self, caster_ref: &CasterRef) -> AbsoluteLocation {
-        self.caster(caster_ref).location
+        match caster_ref {
+            CasterRef::Item(holder, _) => self.monsters[*holder].location(),
+            _ => self.caster(caster_ref).location,
+        }
+    }
+
+    pub fn item(&self, caster_ref: &CasterRef, slot: usize) -> Option<&Item> {
+        self.monsters[self.monster_index(caster_ref)].inventory.get(slot)
+    }
+
+    // Scrolls crumble once they have been read
+    // False if the item isn't a scroll
+    pub fn use_up_scroll(&mut self, holder: usize, slot: usize) -> bool {
+        if let Some(monster) = self.monsters.get_mut(holder) {
+            let is_scroll = match monster.inventory.get(slot) {
+                Some(Item { kind: ItemKind::Scroll, .. }) => true,
+                _ => false,
+            };
+            if is_scroll {
+                monster.inventory.remove(slot);
+                return true
+            }
+        }
+        false
+    }
+
+    pub fn item_spellbook(&self, holder: usize, slot: usize) -> Option<Rc<Spellbook>> {
+        self.monsters.get(holder)
+            .and_then(|monster| monster.inventory.get(slot))
+            .and_then(|item| item.spell.as_ref())
+            .map(|spell| spell.spellbook.clone())
     }
 
     pub fn get_spell(&self, caster_ref: &CasterRef) -> &'static str {
         match caster_ref {
             CasterRef::Player => "wait",
+            CasterRef::Item(_, _) => ITEM_SPELL_ENTRY,
             CasterRef::Monster(index) => {
                 let (my_x, my_y) = self.location(caster_ref);
                 let (player_x, player_y) = self.location(&CasterRef::Player);
                 if player_x > my_x {
-                    if player_y > my_y { "wait" }
-                    else if player_y < my_y { "wait" } 
+                    if player_y > my_y { "down_right" }
+                    else if player_y < my_y { "up_right" } 
                     else { "right" }
                 } else if player_x < my_x {
-                    if player_y > my_y { "wait" }
-                    else if player_y < my_y { "wait" } 
+                    if player_y > my_y { "down_left" }
+                    else if player_y < my_y { "up_left" } 
                     else { "left" }
                 } else {
                     if player_y > my_y { "down" }
@@ -53,10 +162,19 @@ impl Level {
         }
     }
 
+    pub fn monster_index(&self, caster_ref: &CasterRef) -> usize {
+        match caster_ref {
+            CasterRef::Player => 0,
+            CasterRef::Monster(index) => *index,
+            CasterRef::Item(holder, _) => *holder,
+        }
+    }
+
     fn caster(&self, caster_ref: &CasterRef) -> &Caster {
         match caster_ref {
             CasterRef::Player => &self.monsters[0].caster,
             CasterRef::Monster(index) => &self.monsters[*index].caster,
+            CasterRef::Item(holder, slot) => &self.monsters[*holder].inventory[*slot].spell.as_ref().expect("item caster without a spell").caster,
         }
     }
 
@@ -64,6 +182,7 @@ impl Level {
         match caster_ref {
             CasterRef::Player => &mut self.monsters[0].caster,
             CasterRef::Monster(index) => &mut self.monsters[*index].caster,
+            CasterRef::Item(holder, slot) => &mut self.monsters[*holder].inventory[*slot].spell.as_mut().expect("item caster without a spell").caster,
         }
     }
 
@@ -77,394 +196,507 @@ impl Level {
         }
     }
 
+    // Diagonal steps may not squeeze past a wall corner on either side
+    pub fn cuts_corner(&self, from: &AbsoluteLocation, direction: &Direction) -> bool {
+        if !direction.is_diagonal() {
+            return false
+        }
+        let (dx, dy) = direction.location();
+        [(dx, 0), (0, dy)].iter().any(|side| {
+            match self.reify_location(*side, from) {
+                Some(loc) => !self.is_passable(&loc),
+                None => true,
+            }
+        })
+    }
+
     pub fn is_passable(&self, location: &AbsoluteLocation) -> bool {
         let (col, row) = *location;
-        !self.terrain[col][row].is_wall 
+        self.terrain[col][row].kind.is_passable()
+    }
+
+    pub fn is_transparent(&self, location: &AbsoluteLocation) -> bool {
+        let (col, row) = *location;
+        self.terrain[col][row].kind.is_transparent()
+    }
+
+    pub fn tile(&self, location: &AbsoluteLocation) -> Tile {
+        let (col, row) = *location;
+        self.terrain[col][row]
+    }
+
+    // Returns the tile that was there, walls and doors around it join up again
+    pub fn set_tile(&mut self, location: &AbsoluteLocation, tile: Tile) -> Tile {
+        let (col, row) = *location;
+        let before = self.terrain[col][row];
+        self.terrain[col][row] = tile;
+        self.autotile_walls();
+        before
     }
 
     pub fn is_monster(&self, location: &AbsoluteLocation) -> bool {
         self.monsters.iter().any(|monster| { monster.location() == *location })
     }
 
-    pub fn damage(&mut self, location: &AbsoluteLocation, damage: u32) {
+    // Returns the monster if it died
+    pub fn damage(&mut self, location: &AbsoluteLocation, damage: u32) -> Option<Monster> {
         let index = self.monsters.iter().position(|monster| { monster.location() == *location });
         if let Some(index) = index {
             // TODO implement AC and such
-            self.monsters[index].stats.current_hp -= damage;
-            if self.monsters[index].stats.current_hp <= 0 {
-                self.monsters.remove(index);
-                // TODO handle player death
+            let hp = self.monsters[index].stats.current_hp;
+            self.monsters[index].stats.current_hp = hp.saturating_sub(damage);
+            // A dead player stays where they are, so the player is always first
+            if self.monsters[index].is_dead() && index != 0 {
+                let mut dead = self.monsters.remove(index);
+                for mut item in dead.inventory.drain(..) {
+                    item.equipped = false;
+                    self.items.push((*location, item));
+                }
+                return Some(dead);
             }
         }
+        None
     }
 
-    pub fn stupid() -> Level {
-        Level {
-            /*
-            monster_list: MonsterList::with_monster_locations(vec!(
-                (MonsterType::Player, (7, 7)), 
-                (MonsterType::Ant, (12, 12)),
-            )),
-            */
-            monsters: vec!(Monster::player((7, 7)), Monster::ant((12, 12))),
-            terrain: [
-                [Tile::nothing(); 20],
-                [Tile::nothing(); 20],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::top_left_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::bot_left_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::horiz_wall(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::floor(),
-                    Tile::horiz_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [
-                    Tile::nothing(),
-                    Tile::nothing(),
-                    Tile::top_right_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::vert_wall(),
-                    Tile::bot_right_wall(),
-                    Tile::nothing(),
-                    Tile::nothing(),
-                ],
-                [Tile::nothing(); 20],
-                [Tile::nothing(); 20],
-            ],
+    pub fn pick_up(&mut self, caster_ref: &CasterRef) -> Result<String, String> {
+        let location = self.location(caster_ref);
+        match self.items.iter().position(|(loc, _)| *loc == location) {
+            Some(position) => {
+                let (_, item) = self.items.remove(position);
+                let name = item.name.clone();
+                let index = self.monster_index(caster_ref);
+                self.monsters[index].inventory.push(item);
+                Ok(name)
+            },
+            None => Err("There is nothing here to pick up".to_string()),
         }
     }
+
+    pub fn drop_item(&mut self, caster_ref: &CasterRef, slot: usize) -> Result<String, String> {
+        let location = self.location(caster_ref);
+        let index = self.monster_index(caster_ref);
+        if slot >= self.monsters[index].inventory.len() {
+            return Err("No such item".to_string())
+        }
+        if self.monsters[index].inventory[slot].equipped {
+            self.use_item(caster_ref, slot)?;
+        }
+        let item = self.monsters[index].inventory.remove(slot);
+        let name = item.name.clone();
+        self.items.push((location, item));
+        Ok(name)
+    }
+
+    // Equipment is toggled on and off, everything else is used up or spends a charge
+    pub fn use_item(&mut self, caster_ref: &CasterRef, slot: usize) -> Result<String, String> {
+        let index = self.monster_index(caster_ref);
+        let monster = &mut self.monsters[index];
+        if slot >= monster.inventory.len() {
+            return Err("No such item".to_string())
+        }
+        let kind = monster.inventory[slot].kind.clone();
+        let name = monster.inventory[slot].name.clone();
+        match kind {
+            ItemKind::Weapon(_) => {
+                let item = &mut monster.inventory[slot];
+                item.equipped = !item.equipped;
+                Ok(if item.equipped { format!("You wield the {}", name) } else { format!("You put away the {}", name) })
+            },
+            ItemKind::Armor(bonus) => {
+                let item = &mut monster.inventory[slot];
+                item.equipped = !item.equipped;
+                if item.equipped {
+                    monster.stats.ac = monster.stats.ac.saturating_sub(bonus);
+                    Ok(format!("You put on the {}", name))
+                } else {
+                    monster.stats.ac += bonus;
+                    Ok(format!("You take off the {}", name))
+                }
+            },
+            ItemKind::Scroll => {
+                monster.inventory.remove(slot);
+                Ok(format!("You read the {} and it crumbles to dust", name))
+            },
+            // Enchanted wands and scrolls are cast by the spell engine instead
+            ItemKind::Wand => Ok(format!("You wave the {} but nothing happens", name)),
+            ItemKind::EnergyPotion(energy) => {
+                monster.inventory.remove(slot);
+                monster.caster.restore(energy);
+                Ok(format!("You drink the {} and feel energized", name))
+            },
+        }
+    }
+
+    // Every transparent tile within `radius` steps (diagonals count as one step) of `center` that
+    // it can see. Nothing is further away than the size of the level, so bigger radii change nothing.
+    pub fn radius_area(&self, center: &AbsoluteLocation, radius: usize) -> Vec<AbsoluteLocation> {
+        let radius = radius.min(LEVEL_SIZE) as isize;
+        let mut area = Vec::new();
+        for dx in -radius..=radius {
+            for dy in -radius..=radius {
+                if let Some(loc) = self.reify_location((dx, dy), center) {
+                    if self.is_transparent(&loc) && self.in_line_of_sight(center, &loc) {
+                        area.push(loc);
+                    }
+                }
+            }
+        }
+        area
+    }
+
+    // Tiles in a straight line away from `origin`, stopping at the first one that isn't transparent
+    pub fn line_area(&self, origin: &AbsoluteLocation, direction: &Direction, length: usize) -> Vec<AbsoluteLocation> {
+        let (dx, dy) = direction.location();
+        let mut area = Vec::new();
+        for step in 1..=length.min(LEVEL_SIZE) as isize {
+            match self.reify_location((dx * step, dy * step), origin) {
+                Some(loc) if self.is_transparent(&loc) => area.push(loc),
+                _ => break,
+            }
+        }
+        area
+    }
+
+    // Transparent tiles within `length` steps of `origin`, at most 45 degrees off `direction` and
+    // in sight of `origin`
+    pub fn cone_area(&self, origin: &AbsoluteLocation, direction: &Direction, length: usize) -> Vec<AbsoluteLocation> {
+        let (dx, dy) = direction.location();
+        let length = length.min(LEVEL_SIZE) as isize;
+        let mut area = Vec::new();
+        for ox in -length..=length {
+            for oy in -length..=length {
+                let dot = ox * dx + oy * dy;
+                // cos^2 of the angle between the offset and the direction must be at least 1/2
+                if dot <= 0 || 2 * dot * dot < (ox * ox + oy * oy) * (dx * dx + dy * dy) {
+                    continue;
+                }
+                if let Some(loc) = self.reify_location((ox, oy), origin) {
+                    if self.is_transparent(&loc) && self.in_line_of_sight(origin, &loc) {
+                        area.push(loc);
+                    }
+                }
+            }
+        }
+        area
+    }
+
+    // Whether the tiles on the straight line between `from` and `to`, not counting either end,
+    // are all transparent. Points on the line are rounded to the nearest tile, halfway points away
+    // from `from`, so a line can pass between two walls that touch at the corners.
+    pub fn in_line_of_sight(&self, from: &AbsoluteLocation, to: &AbsoluteLocation) -> bool {
+        let (dx, dy) = (to.0 as isize - from.0 as isize, to.1 as isize - from.1 as isize);
+        let steps = dx.abs().max(dy.abs());
+        let nearest = |delta: isize, step: isize| delta.signum() * ((2 * delta.abs() * step + steps) / (2 * steps));
+        (1..steps).all(|step| match self.reify_location((nearest(dx, step), nearest(dy, step)), from) {
+            Some(loc) => self.is_transparent(&loc),
+            None => false,
+        })
+    }
+
+    // Friendly fire rules: area effects never hit the caster or anyone on the caster's side
+    pub fn area_targets(&self, caster_ref: &CasterRef, area: &[AbsoluteLocation]) -> Vec<AbsoluteLocation> {
+        let caster_index = self.monster_index(caster_ref);
+        let faction = self.monsters[caster_index].faction();
+        self.monsters.iter().enumerate()
+            .filter(|(index, monster)| *index != caster_index && monster.faction() != faction)
+            .map(|(_, monster)| monster.location())
+            .filter(|loc| area.contains(loc))
+            .collect()
+    }
+
+    // A level file has `name = value` lines with `#` comments (see `strip_comment`), then a line
+    // saying `map` and the map itself with one character per tile. The settings are:
+    //   depth = <number>
+    //   legend = <character> <names...>   a map character for a terrain kind (floor if none is
+    //                                     named) with monsters and items standing on it
+    //   monster = <name> <col> <row>
+    //   item = <name> <col> <row>
+    // `DEFAULT_LEGEND` has the characters every level file knows.
+    pub fn parse(source: &str) -> Result<Level, String> {
+        let mut legend = HashMap::new();
+        for (symbol, name) in DEFAULT_LEGEND.iter() {
+            legend.insert(*symbol, legend_entry(&[*name]).unwrap());
+        }
+        let mut level = Level::new(1);
+        let mut placements = Vec::new();
+        let mut lines = source.lines().enumerate();
+        let mut found_map = false;
+        for (i, line) in lines.by_ref() {
+            let line_num = i + 1;
+            let line = line.trim();
+            match strip_comment(line, 0).trim() {
+                "" => continue,
+                "map" => {
+                    found_map = true;
+                    break;
+                },
+                _ => (),
+            }
+            let mut sides = line.splitn(2, '=').map(|side| side.trim());
+            let name = strip_comment(sides.next().unwrap(), 0).trim();
+            let value = match sides.next() {
+                Some(value) => strip_comment(value, if name == "legend" { 1 } else { 0 }).trim(),
+                None => "",
+            };
+            if name.is_empty() || value.is_empty() {
+                return Err(format!("line {}: expected `name = value` or `map`", line_num))
+            }
+            let words: Vec<&str> = value.split_whitespace().collect();
+            match name {
+                "depth" => level.depth = value.parse::<u32>().map_err(|_| format!("line {}: depth '{}' is not a number", line_num, value))?,
+                "legend" => {
+                    let mut symbol = words[0].chars();
+                    let symbol = match (symbol.next(), symbol.next()) {
+                        (Some(symbol), None) => symbol,
+                        _ => return Err(format!("line {}: '{}' is not a single character", line_num, words[0])),
+                    };
+                    legend.insert(symbol, legend_entry(&words[1..]).map_err(|err| format!("line {}: {}", line_num, err))?);
+                },
+                "monster" | "item" => {
+                    let location = match words.as_slice() {
+                        [_, col, row] => match (col.parse::<usize>(), row.parse::<usize>()) {
+                            (Ok(col), Ok(row)) if col < LEVEL_SIZE && row < LEVEL_SIZE => (col, row),
+                            _ => return Err(format!("line {}: ({}, {}) is not on the level", line_num, col, row)),
+                        },
+                        _ => return Err(format!("line {}: expected `{} = <name> <col> <row>`", line_num, name)),
+                    };
+                    let is_monster = Monster::from_name(words[0], location).is_some();
+                    if is_monster != (name == "monster") || (!is_monster && Item::from_name(words[0]).is_none()) {
+                        return Err(format!("line {}: unknown {} '{}'", line_num, name, words[0]))
+                    }
+                    placements.push((line_num, words[0], location));
+                },
+                _ => return Err(format!("line {}: unknown setting '{}'", line_num, name)),
+            }
+        }
+        if !found_map {
+            return Err("no `map` line".to_string())
+        }
+        for (row, (i, line)) in lines.enumerate() {
+            let line_num = i + 1;
+            if row >= LEVEL_SIZE {
+                return Err(format!("line {}: the map is more than {} lines long", line_num, LEVEL_SIZE))
+            }
+            for (col, c) in line.chars().enumerate() {
+                if col >= LEVEL_SIZE {
+                    return Err(format!("line {}: more than {} columns", line_num, LEVEL_SIZE))
+                }
+                let (kind, names) = legend.get(&c).ok_or_else(|| format!("line {}: '{}' is not in the legend", line_num, c))?;
+                level.terrain[col][row] = Tile::new(*kind);
+                for name in names.iter() {
+                    level.spawn(name, (col, row)).map_err(|err| format!("line {}: {}", line_num, err))?;
+                }
+            }
+        }
+        for (line_num, name, location) in placements {
+            level.spawn(name, location).map_err(|err| format!("line {}: {}", line_num, err))?;
+        }
+        if !level.monsters.first().map(|monster| monster.mtype == MonsterType::Player).unwrap_or(false) {
+            return Err("no player on the level".to_string())
+        }
+        level.autotile_walls();
+        Ok(level)
+    }
+
+    // Adds the monster or item called `name`, keeping the player first among the monsters.
+    // Nothing can be put where there's no room to stand.
+    fn spawn(&mut self, name: &str, location: AbsoluteLocation) -> Result<(), String> {
+        if !self.is_passable(&location) {
+            let tile = self.terrain[location.0][location.1].kind.name();
+            return Err(format!("{} at ({}, {}) would be in {}", name, location.0, location.1, tile))
+        }
+        if let Some(monster) = Monster::from_name(name, location) {
+            if self.is_monster(&location) {
+                return Err(format!("more than one monster at ({}, {})", location.0, location.1))
+            }
+            if name == MonsterType::Player.name() {
+                if self.monsters.first().map(|first| first.mtype == MonsterType::Player).unwrap_or(false) {
+                    return Err("more than one player".to_string())
+                }
+                self.monsters.insert(0, monster);
+            } else {
+                self.monsters.push(monster);
+            }
+        } else if let Some(item) = Item::from_name(name) {
+            self.items.push((location, item));
+        }
+        Ok(())
+    }
+
+    // Gives each wall the sprite that joins it up with the walls beside it, and each door the
+    // sprite for the way its wall runs, so level generators and editors only have to say where
+    // walls and doors are
+    pub fn autotile_walls(&mut self) {
+        for col in 0..LEVEL_SIZE {
+            for row in 0..LEVEL_SIZE {
+                if !self.terrain[col][row].joins_walls() {
+                    continue;
+                }
+                let wall_at = |dcol: isize, drow: isize| {
+                    let (col, row) = (col as isize + dcol, row as isize + drow);
+                    col >= 0 && row >= 0 && col < LEVEL_SIZE as isize && row < LEVEL_SIZE as isize
+                        && self.terrain[col as usize][row as usize].joins_walls()
+                };
+                let sprite = match self.terrain[col][row].kind {
+                    TileKind::Door(open) => Tile::door_sprite(open, wall_at(0, -1) && wall_at(0, 1)),
+                    _ => Tile::wall_sprite(wall_at(0, -1), wall_at(0, 1), wall_at(-1, 0), wall_at(1, 0)),
+                };
+                self.terrain[col][row].sprite = Some(sprite);
+            }
+        }
+    }
+
+    // The map part of a level file, with monsters drawn over the terrain, items as `*` and traps
+    // nobody has found as floor
+    pub fn to_text(&self) -> Vec<String> {
+        let mut lines: Vec<String> = (0..LEVEL_SIZE).map(|row| (0..LEVEL_SIZE).map(|col| {
+            if let Some(monster) = self.monsters.iter().find(|monster| monster.location() == (col, row)) {
+                monster.mtype.symbol()
+            } else if self.items.iter().any(|(location, _)| *location == (col, row)) {
+                '*'
+            } else {
+                self.terrain[col][row].symbol()
+            }
+        }).collect::<String>().trim_end().to_string()).collect();
+        while lines.last().map(|line| line.is_empty()).unwrap_or(false) {
+            lines.pop();
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tile::{Tile, TileKind};
+    use crate::utils::Direction;
+    use super::Level;
+
+    // Builds terrain from rows of `#` walls, `+` closed doors and `.` floor
+    fn level(rows: &[&str]) -> Level {
+        let mut level = Level::new(1);
+        for (row, line) in rows.iter().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                let kind = match c {
+                    '#' => TileKind::Wall,
+                    '+' => TileKind::Door(false),
+                    _ => TileKind::Floor,
+                };
+                level.terrain[col][row] = Tile::new(kind);
+            }
+        }
+        level.autotile_walls();
+        level
+    }
+
+    fn sprite(level: &Level, col: usize, row: usize) -> &'static str {
+        level.terrain[col][row].sprite.unwrap()
+    }
+
+    #[test]
+    fn walls_join_up_into_rooms() {
+        let level = level(&[
+            "#####",
+            "#.#.#",
+            "#####",
+        ]);
+        assert_eq!(sprite(&level, 0, 0), "wall_top_left");
+        assert_eq!(sprite(&level, 4, 0), "wall_top_right");
+        assert_eq!(sprite(&level, 0, 2), "wall_bottom_left");
+        assert_eq!(sprite(&level, 4, 2), "wall_bottom_right");
+        assert_eq!(sprite(&level, 1, 0), "wall_horiz");
+        assert_eq!(sprite(&level, 2, 0), "wall_t_down");
+        assert_eq!(sprite(&level, 2, 1), "wall_vert");
+        assert_eq!(sprite(&level, 2, 2), "wall_t_up");
+        assert_eq!(sprite(&level, 1, 1), "floor");
+    }
+
+    #[test]
+    fn doors_follow_their_wall_and_count_as_wall() {
+        let level = level(&[
+            "#+#",
+            "+.#",
+            "###",
+        ]);
+        assert_eq!(sprite(&level, 1, 0), "door_closed_horiz");
+        assert_eq!(sprite(&level, 0, 1), "door_closed_vert");
+        assert_eq!(sprite(&level, 0, 0), "wall_top_left");
+    }
+
+    #[test]
+    fn areas_stop_at_walls() {
+        let level = level(&[
+            ".....",
+            "..#..",
+            ".....",
+        ]);
+        let radius = level.radius_area(&(1, 1), 3);
+        assert!(radius.contains(&(1, 1)) && radius.contains(&(3, 0)) && radius.contains(&(3, 2)));
+        assert!(!radius.contains(&(2, 1)) && !radius.contains(&(3, 1)) && !radius.contains(&(4, 2)));
+        let cone = level.cone_area(&(1, 1), &Direction::Right, 3);
+        assert!(cone.contains(&(2, 0)) && cone.contains(&(2, 2)));
+        assert!(!cone.contains(&(3, 1)) && !cone.contains(&(4, 1)));
+    }
+
+    #[test]
+    fn walls_at_the_edge_of_the_level() {
+        let level = level(&["#"]);
+        assert_eq!(sprite(&level, 0, 0), "wall_vert");
+    }
+
+    fn parse_error(source: &str) -> String {
+        Level::parse(source).err().unwrap()
+    }
+
+    #[test]
+    fn level_files_with_legends_and_placements() {
+        let level = Level::parse("\
+# A comment line
+depth = 3 # and a comment after a setting
+legend = k lava ant dagger
+legend = # water # water instead of wall
+item = leather_armor 0 1
+map
+@k#
+.#.
+").unwrap();
+        assert_eq!(level.depth, 3);
+        assert_eq!(level.tile(&(1, 0)).kind, TileKind::Lava);
+        assert_eq!(level.tile(&(2, 0)).kind, TileKind::Water);
+        assert_eq!(level.tile(&(0, 1)).kind, TileKind::Floor);
+        let monsters: Vec<_> = level.monsters.iter().map(|monster| (monster.mtype.name(), monster.location())).collect();
+        assert_eq!(monsters, vec!(("player", (0, 0)), ("ant", (1, 0))));
+        let items: Vec<_> = level.items.iter().map(|(location, item)| (item.sprite, *location)).collect();
+        assert_eq!(items, vec!(("dagger", (1, 0)), ("leather_armor", (0, 1))));
+    }
+
+    #[test]
+    fn setting_errors() {
+        assert_eq!(parse_error("depth = 1\n"), "no `map` line");
+        assert_eq!(parse_error("depth\nmap\n@"), "line 1: expected `name = value` or `map`");
+        assert_eq!(parse_error("depth = # deep\nmap\n@"), "line 1: expected `name = value` or `map`");
+        assert_eq!(parse_error("depth = deep\nmap\n@"), "line 1: depth 'deep' is not a number");
+        assert_eq!(parse_error("colour = red\nmap\n@"), "line 1: unknown setting 'colour'");
+        assert_eq!(parse_error("map\n."), "no player on the level");
+    }
+
+    #[test]
+    fn legend_errors() {
+        assert_eq!(parse_error("legend = xy floor\nmap\n@"), "line 1: 'xy' is not a single character");
+        assert_eq!(parse_error("legend = ## floor\nmap\n@"), "line 1: '##' is not a single character");
+        assert_eq!(parse_error("legend = x floor\n\nlegend = y lava water\nmap\n@"), "line 3: more than one terrain kind");
+        assert_eq!(parse_error("map\n@\n.x"), "line 3: 'x' is not in the legend");
+    }
+
+    #[test]
+    fn placement_errors() {
+        assert_eq!(parse_error("monster = ant 1\nmap\n@"), "line 1: expected `monster = <name> <col> <row>`");
+        assert_eq!(parse_error("item = dagger 1 999\nmap\n@"), "line 1: (1, 999) is not on the level");
+        assert_eq!(parse_error("monster = dagger 1 1\nmap\n@"), "line 1: unknown monster 'dagger'");
+        assert_eq!(parse_error("item = ant 1 1\nmap\n@"), "line 1: unknown item 'ant'");
+        assert_eq!(parse_error("monster = ant 0 0\nmap\n@"), "line 1: more than one monster at (0, 0)");
+        assert_eq!(parse_error("map\n@@"), "line 2: more than one player");
+        assert_eq!(parse_error("monster = ant 1 0\nmap\n@#"), "line 1: ant at (1, 0) would be in wall");
+        assert_eq!(parse_error("item = dagger 0 1\nmap\n@"), "line 1: dagger at (0, 1) would be in solid rock");
+        assert_eq!(parse_error("legend = x water ant\nmap\n@\n.x"), "line 4: ant at (1, 1) would be in water");
+    }
 }
@@ -0,0 +1,391 @@
+use std::fmt;
+use std::str::CharIndices;
+use std::iter::Peekable;
+
+use crate::spells::CompileError;
+
+// Mnemonics and keywords of the cmdlist language, matched case insensitively
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Keyword {
+    PushVal,
+    Push,
+    Pop,
+    Peek,
+    Poke,
+    Copy,
+    Store,
+    Call,
+    Return,
+    Noop,
+    Jump,
+    JumpIfGt,
+    Add,
+    Mul,
+    Sub,
+    Div,
+    Mod,
+    And,
+    Or,
+    Xor,
+    Not,
+    PromptDirection,
+    PromptLocation,
+    MoveCursor,
+    Damage,
+    Move,
+    Pickup,
+    Drop,
+    UseItem,
+    DamageRadius,
+    DamageLine,
+    DamageCone,
+    Open,
+    Close,
+    Dig,
+    BuildWall,
+    Ignite,
+    QueryEnergy,
+    QueryLocationSelf,
+    QueryLocationCursor,
+    IsValid,
+    IsPassable,
+    IsMonster,
+    TileKind,
+    Const,
+    Macro,
+    Include,
+}
+
+const KEYWORDS: &[(&str, Keyword)] = &[
+    ("push_val", Keyword::PushVal),
+    ("push", Keyword::Push),
+    ("pop", Keyword::Pop),
+    ("peek", Keyword::Peek),
+    ("poke", Keyword::Poke),
+    ("copy", Keyword::Copy),
+    ("store", Keyword::Store),
+    ("call", Keyword::Call),
+    ("return", Keyword::Return),
+    ("noop", Keyword::Noop),
+    ("jump", Keyword::Jump),
+    ("jump_if_gt", Keyword::JumpIfGt),
+    ("add", Keyword::Add),
+    ("mul", Keyword::Mul),
+    ("sub", Keyword::Sub),
+    ("div", Keyword::Div),
+    ("mod", Keyword::Mod),
+    ("and", Keyword::And),
+    ("or", Keyword::Or),
+    ("xor", Keyword::Xor),
+    ("not", Keyword::Not),
+    ("prompt_direction", Keyword::PromptDirection),
+    ("prompt_location", Keyword::PromptLocation),
+    ("move_cursor", Keyword::MoveCursor),
+    ("damage", Keyword::Damage),
+    ("move", Keyword::Move),
+    ("pickup", Keyword::Pickup),
+    ("drop", Keyword::Drop),
+    ("use_item", Keyword::UseItem),
+    ("damage_radius", Keyword::DamageRadius),
+    ("damage_line", Keyword::DamageLine),
+    ("damage_cone", Keyword::DamageCone),
+    ("open", Keyword::Open),
+    ("close", Keyword::Close),
+    ("dig", Keyword::Dig),
+    ("build_wall", Keyword::BuildWall),
+    ("ignite", Keyword::Ignite),
+    ("query_energy", Keyword::QueryEnergy),
+    ("query_location_self", Keyword::QueryLocationSelf),
+    ("query_location_cursor", Keyword::QueryLocationCursor),
+    ("is_valid", Keyword::IsValid),
+    ("is_passable", Keyword::IsPassable),
+    ("is_monster", Keyword::IsMonster),
+    ("tile_kind", Keyword::TileKind),
+    ("const", Keyword::Const),
+    ("macro", Keyword::Macro),
+    ("include", Keyword::Include),
+];
+
+impl Keyword {
+    pub fn name(&self) -> &'static str {
+        KEYWORDS.iter().find(|(_, keyword)| keyword == self).map(|(name, _)| *name).unwrap()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tok<'input> {
+    Keyword(Keyword),
+    Name(&'input str),
+    Register(usize),
+    Num(i32),
+    Const(&'input str), // `#name`, without the `#`
+    Group(&'input str), // `(...)`, macro parameters or arguments
+    Body(&'input str), // `{...}`, a macro body
+    Path(&'input str), // `"..."`, an include
+    Colon,
+    Semicolon,
+    Equals,
+    Bang,
+}
+
+impl<'input> fmt::Display for Tok<'input> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tok::Keyword(keyword) => write!(f, "{}", keyword.name()),
+            Tok::Name(name) => write!(f, "{}", name),
+            Tok::Register(reg) => write!(f, "{}", (b'a' + *reg as u8) as char),
+            Tok::Num(value) => write!(f, "{}", value),
+            Tok::Const(name) => write!(f, "#{}", name),
+            Tok::Group(text) | Tok::Body(text) | Tok::Path(text) => write!(f, "{}", text),
+            Tok::Colon => write!(f, ":"),
+            Tok::Semicolon => write!(f, ";"),
+            Tok::Equals => write!(f, "="),
+            Tok::Bang => write!(f, "!"),
+        }
+    }
+}
+
+pub type Spanned<'input> = Result<(usize, Tok<'input>, usize), CompileError>;
+
+// Whitespace is ignored, and `//` comments out the rest of the line. So does `#`, except where a
+// value goes: after `push_val`, `store`, `peek`, the register of `poke` and the `=` of a const,
+// `#name` is a constant. That way `store #left d; #cast fire` ends in a comment.
+pub struct Lexer<'input> {
+    source: &'input str,
+    chars: Peekable<CharIndices<'input>>,
+    recent: (Option<Tok<'input>>, Option<Tok<'input>>), // the token before last, the last token
+}
+
+impl<'input> Lexer<'input> {
+    pub fn new(source: &'input str) -> Lexer<'input> {
+        Lexer {
+            source: source,
+            chars: source.char_indices().peekable(),
+            recent: (None, None),
+        }
+    }
+
+    fn wants_value(&self) -> bool {
+        match &self.recent {
+            (_, Some(Tok::Keyword(Keyword::PushVal))) | (_, Some(Tok::Keyword(Keyword::Store)))
+                | (_, Some(Tok::Keyword(Keyword::Peek))) | (_, Some(Tok::Equals)) => true,
+            (Some(Tok::Keyword(Keyword::Poke)), Some(Tok::Register(_))) => true,
+            _ => false,
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some((_, c)) = self.chars.next() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    // Consumes characters while `pred` holds and returns the end offset
+    fn take_while<F: Fn(char) -> bool>(&mut self, start: usize, pred: F) -> usize {
+        let mut end = start;
+        while let Some(&(i, c)) = self.chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            end = i + c.len_utf8();
+            self.chars.next();
+        }
+        end
+    }
+
+    // Reads up to and including `close`; groups and bodies don't nest
+    fn delimited(&mut self, start: usize, close: char, what: &str) -> Spanned<'input> {
+        while let Some((i, c)) = self.chars.next() {
+            if c == close {
+                let text = &self.source[start..i + 1];
+                let tok = match close {
+                    ')' => Tok::Group(text),
+                    '}' => Tok::Body(text),
+                    _ => Tok::Path(text),
+                };
+                return Ok((start, tok, i + 1))
+            }
+        }
+        Err(error(start, format!("Unterminated {}", what)))
+    }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+    type Item = Spanned<'input>;
+
+    fn next(&mut self) -> Option<Spanned<'input>> {
+        let spanned = self.lex()?;
+        if let Ok((_, tok, _)) = &spanned {
+            self.recent = (self.recent.1.take(), Some(tok.clone()));
+        }
+        Some(spanned)
+    }
+}
+
+impl<'input> Lexer<'input> {
+    fn lex(&mut self) -> Option<Spanned<'input>> {
+        loop {
+            let (start, c) = self.chars.next()?;
+            let is_word_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+            return Some(match c {
+                c if c.is_whitespace() => continue,
+                '/' if self.chars.peek().map(|&(_, c)| c) == Some('/') => {
+                    self.skip_line();
+                    continue
+                },
+                '#' => match self.chars.peek().map(|&(_, c)| c) {
+                    Some(c) if c.is_ascii_alphabetic() && self.wants_value() => {
+                        let end = self.take_while(start + 1, is_word_char);
+                        Ok((start, Tok::Const(&self.source[start + 1..end]), end))
+                    },
+                    _ => {
+                        self.skip_line();
+                        continue
+                    },
+                },
+                ':' => Ok((start, Tok::Colon, start + 1)),
+                ';' => Ok((start, Tok::Semicolon, start + 1)),
+                '=' => Ok((start, Tok::Equals, start + 1)),
+                '!' => Ok((start, Tok::Bang, start + 1)),
+                '(' => self.delimited(start, ')', "macro arguments"),
+                '{' => self.delimited(start, '}', "macro body"),
+                '"' => self.delimited(start, '"', "string"),
+                '-' | '0'..='9' => {
+                    let end = self.take_while(start + 1, |c| c.is_ascii_digit());
+                    let text = &self.source[start..end];
+                    match text.parse::<i32>() {
+                        Ok(value) => Ok((start, Tok::Num(value), end)),
+                        Err(_) => Err(error(start, format!("Invalid number {}", text))),
+                    }
+                },
+                c if c.is_ascii_alphabetic() || c == '_' => {
+                    let end = self.take_while(start + c.len_utf8(), is_word_char);
+                    let word = &self.source[start..end];
+                    let lower = word.to_ascii_lowercase();
+                    let tok = if let Some((_, keyword)) = KEYWORDS.iter().find(|(name, _)| *name == lower) {
+                        Tok::Keyword(*keyword)
+                    } else if word.len() == 1 && c.is_ascii_lowercase() {
+                        Tok::Register((c as u8 - b'a') as usize)
+                    } else {
+                        Tok::Name(word)
+                    };
+                    Ok((start, tok, end))
+                },
+                c => Err(error(start, format!("Unexpected character '{}'", c))),
+            })
+        }
+    }
+}
+
+fn error(offset: usize, message: String) -> CompileError {
+    CompileError {
+        offset: Some(offset),
+        message: message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lexer, Tok, Keyword, KEYWORDS};
+
+    fn tokens<'a>(source: &'a str) -> Vec<Tok<'a>> {
+        Lexer::new(source).map(|spanned| spanned.unwrap().1).collect()
+    }
+
+    #[test]
+    fn commands_and_spans() {
+        let spanned: Vec<_> = Lexer::new("push_val -3;\n:fire: add a b c;").map(Result::unwrap).collect();
+        assert_eq!(spanned, vec!(
+            (0, Tok::Keyword(Keyword::PushVal), 8),
+            (9, Tok::Num(-3), 11),
+            (11, Tok::Semicolon, 12),
+            (13, Tok::Colon, 14),
+            (14, Tok::Name("fire"), 18),
+            (18, Tok::Colon, 19),
+            (20, Tok::Keyword(Keyword::Add), 23),
+            (24, Tok::Register(0), 25),
+            (26, Tok::Register(1), 27),
+            (28, Tok::Register(2), 29),
+            (29, Tok::Semicolon, 30),
+        ));
+    }
+
+    #[test]
+    fn keywords_ignore_case_but_registers_dont() {
+        assert_eq!(tokens("PUSH a A"), vec!(Tok::Keyword(Keyword::Push), Tok::Register(0), Tok::Name("A")));
+        for (name, keyword) in KEYWORDS.iter() {
+            assert_eq!(tokens(name), vec!(Tok::Keyword(*keyword)));
+            assert_eq!(keyword.name(), *name);
+        }
+    }
+
+    #[test]
+    fn comments_and_constants() {
+        assert_eq!(tokens("noop; // call x;\n# a comment\nstore #wall a; #\nnoop;"), vec!(
+            Tok::Keyword(Keyword::Noop),
+            Tok::Semicolon,
+            Tok::Keyword(Keyword::Store),
+            Tok::Const("wall"),
+            Tok::Register(0),
+            Tok::Semicolon,
+            Tok::Keyword(Keyword::Noop),
+            Tok::Semicolon,
+        ));
+    }
+
+    #[test]
+    fn hash_words_outside_values_are_comments() {
+        assert_eq!(tokens("store 1 a; #cast fire\n# heal first\n#wall\nnoop; #left"), vec!(
+            Tok::Keyword(Keyword::Store),
+            Tok::Num(1),
+            Tok::Register(0),
+            Tok::Semicolon,
+            Tok::Keyword(Keyword::Noop),
+            Tok::Semicolon,
+        ));
+        assert_eq!(tokens("push_val #up; peek #down b; poke a #left; const x = #right;"), vec!(
+            Tok::Keyword(Keyword::PushVal),
+            Tok::Const("up"),
+            Tok::Semicolon,
+            Tok::Keyword(Keyword::Peek),
+            Tok::Const("down"),
+            Tok::Register(1),
+            Tok::Semicolon,
+            Tok::Keyword(Keyword::Poke),
+            Tok::Register(0),
+            Tok::Const("left"),
+            Tok::Semicolon,
+            Tok::Keyword(Keyword::Const),
+            Tok::Register(23),
+            Tok::Equals,
+            Tok::Const("right"),
+            Tok::Semicolon,
+        ));
+    }
+
+    #[test]
+    fn macro_groups_bodies_and_paths() {
+        assert_eq!(tokens("macro sum(x, y) { add x y r; } include \"lib.txt\";"), vec!(
+            Tok::Keyword(Keyword::Macro),
+            Tok::Name("sum"),
+            Tok::Group("(x, y)"),
+            Tok::Body("{ add x y r; }"),
+            Tok::Keyword(Keyword::Include),
+            Tok::Path("\"lib.txt\""),
+            Tok::Semicolon,
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        let first_error = |source| Lexer::new(source).filter_map(Result::err).next().unwrap();
+        let err = first_error("push_val 99999999999;");
+        assert_eq!((err.offset, err.message.as_str()), (Some(9), "Invalid number 99999999999"));
+        let err = first_error("noop; { never closed");
+        assert_eq!((err.offset, err.message.as_str()), (Some(6), "Unterminated macro body"));
+        let err = first_error("noop $");
+        assert_eq!((err.offset, err.message.as_str()), (Some(5), "Unexpected character '$'"));
+    }
+}
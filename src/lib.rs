@@ -0,0 +1,31 @@
+extern crate quicksilver;
+#[cfg(unix)] extern crate libc;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+
+#[macro_use] extern crate lalrpop_util;
+
+pub mod tile;
+pub mod monster;
+pub mod level;
+pub mod world;
+pub mod game;
+pub mod spells;
+pub mod utils;
+pub mod constants;
+pub mod events;
+pub mod keymap;
+pub mod item;
+pub mod learning;
+pub mod messages;
+pub mod save;
+pub mod editor;
+pub mod macros;
+pub mod lexer;
+pub mod script;
+pub mod analysis;
+pub mod journal;
+pub mod animation;
+pub mod terrain_mesh;
+pub mod tileset;
+#[cfg(unix)] pub mod terminal;
@@ -0,0 +1,268 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::spells::{cmdlist, CompileError, Statement, Span};
+use crate::lexer::Lexer;
+use crate::utils::Direction;
+use crate::tile::TileKind;
+use crate::script;
+
+const MAX_NESTING: usize = 32; // stops macros and includes from expanding forever
+const MAX_EXPANSION: usize = 100_000; // bytes of macro expansions and included files, stops macros that double up
+
+// Constants, macros and the include loader, shared by everything compiled into one spellbook
+pub struct CompileEnv {
+    consts: HashMap<String, i32>,
+    macros: HashMap<String, (Vec<String>, String)>, // parameter names, body
+    include: Box<dyn Fn(&str) -> Result<String, String>>,
+    nesting: usize,
+    expanded: usize, // bytes so far, see `MAX_EXPANSION`
+}
+
+impl CompileEnv {
+    pub fn new(include: Box<dyn Fn(&str) -> Result<String, String>>) -> CompileEnv {
+        CompileEnv {
+            consts: HashMap::new(),
+            macros: HashMap::new(),
+            include: include,
+            nesting: 0,
+            expanded: 0,
+        }
+    }
+
+    pub fn define_const(&mut self, offset: usize, name: &str, value: i32) -> Result<(), CompileError> {
+        if builtin_const(name).is_some() {
+            return Err(error(offset, format!("const {} is built in", name)))
+        }
+        if self.consts.insert(name.to_string(), value).is_some() {
+            return Err(error(offset, format!("const {} is already defined", name)))
+        }
+        Ok(())
+    }
+
+    // Constants are used as `#NAME`, like the built in direction and tile kind constants
+    pub fn lookup_const(&self, offset: usize, name: &str) -> Result<i32, CompileError> {
+        builtin_const(name).or_else(|| self.consts.get(name).cloned())
+            .ok_or_else(|| error(offset, format!("Unknown const {}", name)))
+    }
+
+    // `params` and `body` still have their parentheses and braces
+    pub fn define_macro(&mut self, offset: usize, name: &str, params: &str, body: &str) -> Result<(), CompileError> {
+        let params = split_group(params);
+        if let Some(param) = params.iter().find(|param| !is_name(param)) {
+            return Err(error(offset, format!("macro {}: '{}' is not a parameter name", name, param)))
+        }
+        let body = body[1..body.len() - 1].to_string();
+        if self.macros.insert(name.to_string(), (params, body)).is_some() {
+            return Err(error(offset, format!("macro {} is already defined", name)))
+        }
+        Ok(())
+    }
+
+    // Each `$param` in the body is replaced by the argument text, so parameters can stand for
+    // values, registers or parts of labels
+    pub fn expand(env: &RefCell<CompileEnv>, span: Span, name: &str, args: &str) -> Result<Vec<Statement>, CompileError> {
+        let offset = span.0;
+        let (params, body) = match env.borrow().macros.get(name) {
+            Some(definition) => definition.clone(),
+            None => return Err(error(offset, format!("Unknown macro {}", name))),
+        };
+        let args = split_group(args);
+        if args.iter().any(|arg| arg.is_empty()) {
+            return Err(error(offset, format!("macro {} is given an empty argument", name)))
+        }
+        if args.len() != params.len() {
+            let message = format!("macro {} takes {} arguments but was given {}", name, params.len(), args.len());
+            return Err(error(offset, message))
+        }
+        // Longest names first so `$d` doesn't eat the start of `$dir`
+        let mut substitutions: Vec<(&String, &String)> = params.iter().zip(args.iter()).collect();
+        substitutions.sort_by_key(|(param, _)| ::std::cmp::Reverse(param.len()));
+        let mut source = body;
+        for (param, arg) in substitutions {
+            source = source.replace(&format!("${}", param), arg);
+        }
+        let context = format!("in macro {}", name);
+        spend(env, offset, &source, &context)?;
+        parse_nested(env, span, &source, &context)
+    }
+
+    // `.script` files are compiled with the higher level language
+    pub fn include(env: &RefCell<CompileEnv>, span: Span, path: &str) -> Result<Vec<Statement>, CompileError> {
+        let offset = span.0;
+        let path = &path[1..path.len() - 1];
+        let source = (env.borrow().include)(path).map_err(|err| error(offset, err))?;
+        let context = format!("in {}", path);
+        spend(env, offset, &source, &context)?;
+        if path.ends_with(".script") {
+            script::compile(&source)
+                .map(|statements| respan(statements, span))
+                .map_err(|err| nested_error(offset, &source, &context, err))
+        } else {
+            parse_nested(env, span, &source, &context)
+        }
+    }
+}
+
+// Errors and spans inside expanded text point at the outermost macro use or include, with the
+// innermost context in error messages
+fn parse_nested(env: &RefCell<CompileEnv>, span: Span, source: &str, context: &str) -> Result<Vec<Statement>, CompileError> {
+    let offset = span.0;
+    if env.borrow().nesting >= MAX_NESTING {
+        return Err(error(offset, format!("{}: nested too deeply", context)))
+    }
+    env.borrow_mut().nesting += 1;
+    let result = cmdlist::CmdListParser::new().parse(env, Lexer::new(source))
+        .map_err(|err| CompileError::from_parse_error(err, source));
+    env.borrow_mut().nesting -= 1;
+    result
+        .map(|statements| respan(statements, span))
+        .map_err(|err| nested_error(offset, source, context, err))
+}
+
+// Counts expanded text against `MAX_EXPANSION`
+fn spend(env: &RefCell<CompileEnv>, offset: usize, source: &str, context: &str) -> Result<(), CompileError> {
+    let mut env = env.borrow_mut();
+    env.expanded += source.len();
+    if env.expanded > MAX_EXPANSION {
+        return Err(error(offset, format!("{}: expands to more than {} bytes", context, MAX_EXPANSION)))
+    }
+    Ok(())
+}
+
+fn respan(statements: Vec<Statement>, span: Span) -> Vec<Statement> {
+    statements.into_iter().map(|(label, cmd, _)| (label, cmd, span)).collect()
+}
+
+fn nested_error(offset: usize, source: &str, context: &str, err: CompileError) -> CompileError {
+    if err.message.starts_with("in ") {
+        return error(offset, err.message)
+    }
+    let message = match err.line_col(source) {
+        Some((line, col)) => format!("{}, line {} column {}: {}", context, line + 1, col + 1, err.message),
+        None => format!("{}: {}", context, err.message),
+    };
+    error(offset, message)
+}
+
+// The comma separated contents of a `(...)` group. Only `()` has no parts, so `(a,,b)` has an
+// empty one.
+fn split_group(group: &str) -> Vec<String> {
+    let inner = group[1..group.len() - 1].trim();
+    if inner.is_empty() {
+        return Vec::new()
+    }
+    inner.split(',').map(|part| part.trim().to_string()).collect()
+}
+
+fn is_name(text: &str) -> bool {
+    text.chars().next().map(|c| c.is_ascii_alphabetic()).unwrap_or(false)
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// The direction and tile kind constants every spellbook knows
+fn builtin_const(name: &str) -> Option<i32> {
+    Direction::from_name(name).map(|direction| direction.num()).or_else(|| TileKind::code_from_name(name))
+}
+
+fn error(offset: usize, message: String) -> CompileError {
+    CompileError {
+        offset: Some(offset),
+        message: message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::level::Level;
+    use crate::spells::{Spellbook, SpellEngine, CasterRef, CompileError};
+
+    fn compile_error(source: &str) -> CompileError {
+        Spellbook::compile(source).err().unwrap()
+    }
+
+    // Where the player ends up after casting `spell`, starting from (1, 1)
+    fn player_after(spellbook: &Spellbook, spell: &str) -> (usize, usize) {
+        let mut engine = SpellEngine::new(Level::parse("map\n...\n.@.\n...\n").unwrap());
+        engine.do_turn(spell, spellbook, &Spellbook::monster_spellbook());
+        engine.level.location(&CasterRef::Player)
+    }
+
+    #[test]
+    fn arguments_are_substituted_into_the_body() {
+        let spellbook = Spellbook::compile("
+            macro go(d, dir) { store $dir $d; move $d; }
+            macro spell(name, dir) { :$name: noop; go!(a, $dir); return; }
+            spell!(east, #right);
+        ").unwrap();
+        // `$d` doesn't eat the start of `$dir`
+        assert_eq!(player_after(&spellbook, "east"), (2, 1));
+    }
+
+    #[test]
+    fn macro_use_errors() {
+        let err = compile_error("macro go(d) { move $d; }\ngo!();");
+        assert_eq!((err.offset, err.message.as_str()), (Some(25), "macro go takes 1 arguments but was given 0"));
+        let err = compile_error("go!(a);");
+        assert_eq!((err.offset, err.message.as_str()), (Some(0), "Unknown macro go"));
+        let err = compile_error("macro go(d) { move $d; }\nmacro go() { return; }");
+        assert_eq!((err.offset, err.message.as_str()), (Some(25), "macro go is already defined"));
+    }
+
+    #[test]
+    fn only_empty_parentheses_have_no_arguments() {
+        assert!(Spellbook::compile("macro stay() { return; } :wait: noop; stay!( );").is_ok());
+        let err = compile_error("macro go(a, b, c) { return; } go!(a,,b);");
+        assert_eq!(err.message, "macro go is given an empty argument");
+        let err = compile_error("macro go(a,) { return; }");
+        assert_eq!(err.message, "macro go: '' is not a parameter name");
+    }
+
+    #[test]
+    fn includes_spell_and_script_files() {
+        let include = Box::new(|path: &str| match path {
+            "step.spell" => Ok(":step: store #right a; move a; return;".to_string()),
+            "hop.script" => Ok("spell hop() { move(#down); }".to_string()),
+            _ => Err(format!("no file {}", path)),
+        });
+        let spellbook = Spellbook::compile_with("include \"step.spell\"; include \"hop.script\";", include).unwrap();
+        assert_eq!(player_after(&spellbook, "step"), (2, 1));
+        assert_eq!(player_after(&spellbook, "hop"), (1, 2));
+        let err = Spellbook::compile_with(":ok: return;\ninclude \"gone.spell\";", Box::new(|path: &str| Err(format!("no file {}", path)))).err().unwrap();
+        assert_eq!((err.offset, err.message.as_str()), (Some(13), "no file gone.spell"));
+    }
+
+    #[test]
+    fn errors_inside_expansions_point_at_the_use() {
+        let err = compile_error("macro bad() {\n  return;\n  move;\n}\n:ok: return;\nbad!();");
+        assert_eq!(err.offset, Some(47));
+        assert!(err.message.starts_with("in macro bad, line 3 column 7: "), "{}", err.message);
+        // Only the innermost macro is named
+        let err = compile_error("macro bad() { move; } macro outer() { bad!(); } outer!();");
+        assert_eq!(err.offset, Some(48));
+        assert!(err.message.starts_with("in macro bad, line 1 column "), "{}", err.message);
+    }
+
+    #[test]
+    fn expansion_is_limited() {
+        let err = compile_error("macro forever() { forever!(); } forever!();");
+        assert_eq!(err.message, "in macro forever: nested too deeply");
+        // Each macro uses the one before twice, so the last one would be a million returns
+        let mut source = "macro m0() { return; }\n".to_string();
+        for i in 1..21 {
+            source.push_str(&format!("macro m{}() {{ m{}!(); m{}!(); }}\n", i, i - 1, i - 1));
+        }
+        source.push_str(":big: noop; m20!();");
+        let err = compile_error(&source);
+        assert!(err.message.ends_with("expands to more than 100000 bytes"), "{}", err.message);
+    }
+
+    #[test]
+    fn constants_cannot_shadow_built_in_ones() {
+        let err = Spellbook::compile("const wall = 40;").err().unwrap();
+        assert_eq!((err.offset, err.message.as_str()), (Some(0), "const wall is built in"));
+        let err = Spellbook::compile("const left = 1;").err().unwrap();
+        assert_eq!(err.message, "const left is built in");
+        assert!(Spellbook::compile("const walls = 40; :build: store #walls a; store #wall b; return;").is_ok());
+    }
+}
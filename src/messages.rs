@@ -0,0 +1,52 @@
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MessageCategory {
+    Combat,
+    Item,
+    SpellError,
+    System,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub turn: u32,
+    pub category: MessageCategory,
+    pub text: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MessageLog {
+    messages: Vec<Message>,
+}
+
+impl MessageLog {
+    pub fn new() -> MessageLog {
+        MessageLog {
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, turn: u32, category: MessageCategory, text: String) {
+        self.messages.push(Message {
+            turn: turn,
+            category: category,
+            text: text,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    // The last `count` messages, oldest first
+    pub fn recent(&self, count: usize) -> &[Message] {
+        let start = self.messages.len().saturating_sub(count);
+        &self.messages[start..]
+    }
+
+    // Up to `count` messages ending `offset` messages before the newest one, oldest first
+    pub fn page(&self, offset: usize, count: usize) -> &[Message] {
+        let end = self.messages.len().saturating_sub(offset);
+        let start = end.saturating_sub(count);
+        &self.messages[start..end]
+    }
+}
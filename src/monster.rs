@@ -1,13 +1,19 @@
 use crate::spells::Caster;
+use crate::item::{Item, ItemKind};
+use constants::ANT_LORE;
 use crate::utils::AbsoluteLocation;
 
 const MAX_PER_LEVEL: usize = 200;
 
+#[derive(Clone)]
 pub struct Monster {
     pub stats: StatBlock,
     pub caster: Caster,
-    pub sprite_index: usize,
+    pub sprite: &'static str,
     pub mtype: MonsterType,
+    pub effects: Vec<StatusEffect>,
+    pub inventory: Vec<Item>,
+    pub lore: Vec<String>, // spells the player discovers by defeating this monster
 }
 
 #[derive(Copy, Clone)]
@@ -18,12 +24,62 @@ pub struct StatBlock {
     // TODO more stuff, resistances, etc.
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    Drained, // energy doesn't regenerate
+}
+
+impl Condition {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Condition::Drained => "drained",
+        }
+    }
+}
+
+// A temporary condition shown in the HUD, removed once it runs out
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StatusEffect {
+    pub condition: Condition,
+    pub turns_left: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MonsterType {
     Player,
     Ant,
 }
 
+#[derive(Copy, Clone, PartialEq)]
+pub enum Faction {
+    Player,
+    Monsters,
+}
+
+impl MonsterType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MonsterType::Player => "player",
+            MonsterType::Ant => "ant",
+        }
+    }
+
+    // Used by the text level format
+    pub fn symbol(&self) -> char {
+        match self {
+            MonsterType::Player => '@',
+            MonsterType::Ant => 'a',
+        }
+    }
+
+    pub fn faction(&self) -> Faction {
+        match self {
+            MonsterType::Player => Faction::Player,
+            MonsterType::Ant => Faction::Monsters,
+        }
+    }
+}
+
 impl Monster {
     pub fn player(location: AbsoluteLocation) -> Monster {
         Monster {
@@ -33,8 +89,11 @@ impl Monster {
                 ac: 10,
             },
             caster: Caster::simple(location, 10),
-            sprite_index: 348,
+            sprite: "player",
             mtype: MonsterType::Player,
+            effects: Vec::new(),
+            inventory: Vec::new(),
+            lore: Vec::new(),
         }
     }
 
@@ -46,12 +105,62 @@ impl Monster {
                 ac: 10,
             },
             caster: Caster::simple(location, 10),
-            sprite_index: 0,
+            sprite: "ant",
             mtype: MonsterType::Ant,
+            effects: Vec::new(),
+            inventory: Vec::new(),
+            lore: ANT_LORE.iter().map(|label| label.to_string()).collect(),
+        }
+    }
+
+    // Used by level files
+    pub fn from_name(name: &str, location: AbsoluteLocation) -> Option<Monster> {
+        match name {
+            "player" => Some(Monster::player(location)),
+            "ant" => Some(Monster::ant(location)),
+            _ => None,
         }
     }
 
     pub fn location(&self) -> AbsoluteLocation {
         self.caster.location
     }
+
+    pub fn faction(&self) -> Faction {
+        self.mtype.faction()
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.stats.current_hp == 0
+    }
+
+    // Extra melee damage from any wielded weapons
+    pub fn weapon_bonus(&self) -> u32 {
+        self.inventory.iter().filter(|item| item.equipped).map(|item| match item.kind {
+            ItemKind::Weapon(bonus) => bonus,
+            _ => 0,
+        }).sum()
+    }
+
+    // The same condition again lasts as long as the longer of the two
+    pub fn afflict(&mut self, condition: Condition, turns: u32) {
+        match self.effects.iter_mut().find(|effect| effect.condition == condition) {
+            Some(effect) => effect.turns_left = effect.turns_left.max(turns),
+            None => self.effects.push(StatusEffect {
+                condition: condition,
+                turns_left: turns,
+            }),
+        }
+    }
+
+    pub fn is_affected(&self, condition: Condition) -> bool {
+        self.effects.iter().any(|effect| effect.condition == condition)
+    }
+
+    pub fn tick_effects(&mut self) {
+        for effect in self.effects.iter_mut() {
+            effect.turns_left = effect.turns_left.saturating_sub(1);
+        }
+        self.effects.retain(|effect| effect.turns_left > 0);
+    }
 }
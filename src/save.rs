@@ -0,0 +1,139 @@
+use quicksilver::saving;
+
+use crate::messages::MessageLog;
+use crate::learning::SpellMemory;
+use crate::level::Level;
+use crate::monster::{Monster, MonsterType, StatusEffect};
+use crate::item::Item;
+use crate::tile::{Tile, TileKind};
+use crate::utils::AbsoluteLocation;
+
+const APP_NAME: &'static str = "arlogue";
+const PROFILE: &'static str = "save";
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveFile {
+    pub turn: u32,
+    pub messages: MessageLog,
+    pub spells: SpellMemory,
+    #[serde(default)]
+    pub custom_spells: String, // source written in the spell editor
+    pub level: SavedLevel,
+}
+
+impl SaveFile {
+    pub fn save(&self) -> Result<(), String> {
+        saving::save(APP_NAME, PROFILE, self).map_err(|err| format!("Could not save: {}", err))
+    }
+
+    pub fn load() -> Result<SaveFile, String> {
+        saving::load(APP_NAME, PROFILE).map_err(|err| format!("Could not load: {}", err))
+    }
+}
+
+// Monsters and items are saved by the names level files use, with whatever has changed since
+// they were made
+#[derive(Serialize, Deserialize)]
+pub struct SavedLevel {
+    depth: u32,
+    terrain: Vec<Vec<TileKind>>, // columns of rows
+    monsters: Vec<SavedMonster>,
+    items: Vec<(AbsoluteLocation, SavedItem)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedMonster {
+    name: String,
+    location: AbsoluteLocation,
+    hp: u32,
+    ac: u8,
+    energy: u32,
+    effects: Vec<StatusEffect>,
+    inventory: Vec<SavedItem>,
+    lore: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedItem {
+    name: String,
+    equipped: bool,
+    charge: Option<u32>, // energy left in wands and scrolls with a spell
+}
+
+impl SavedLevel {
+    pub fn new(level: &Level) -> SavedLevel {
+        SavedLevel {
+            depth: level.depth,
+            terrain: level.terrain.iter().map(|column| column.iter().map(|tile| tile.kind).collect()).collect(),
+            monsters: level.monsters.iter().map(|monster| SavedMonster {
+                name: monster.mtype.name().to_string(),
+                location: monster.location(),
+                hp: monster.stats.current_hp,
+                ac: monster.stats.ac,
+                energy: monster.caster.energy,
+                effects: monster.effects.clone(),
+                inventory: monster.inventory.iter().map(SavedItem::new).collect(),
+                lore: monster.lore.clone(),
+            }).collect(),
+            items: level.items.iter().map(|(location, item)| (*location, SavedItem::new(item))).collect(),
+        }
+    }
+
+    pub fn restore(&self) -> Result<Level, String> {
+        let mut level = Level::new(self.depth);
+        if self.terrain.len() != level.terrain.len() || self.terrain.iter().any(|column| column.len() != level.terrain[0].len()) {
+            return Err("The saved level is the wrong size".to_string())
+        }
+        for (col, column) in self.terrain.iter().enumerate() {
+            for (row, kind) in column.iter().enumerate() {
+                level.terrain[col][row] = Tile::new(*kind);
+            }
+        }
+        level.autotile_walls();
+        let size = level.terrain.len();
+        let on_level = |(col, row): &AbsoluteLocation| *col < size && *row < size;
+        for saved in self.monsters.iter() {
+            if !on_level(&saved.location) {
+                return Err(format!("The saved {} is off the level", saved.name))
+            }
+            let mut monster = Monster::from_name(&saved.name, saved.location).ok_or_else(|| format!("Unknown monster {} in the save", saved.name))?;
+            monster.stats.current_hp = saved.hp;
+            monster.stats.ac = saved.ac;
+            monster.caster.energy = saved.energy;
+            monster.effects = saved.effects.clone();
+            monster.inventory = saved.inventory.iter().map(SavedItem::restore).collect::<Result<_, _>>()?;
+            monster.lore = saved.lore.clone();
+            level.monsters.push(monster);
+        }
+        for (location, saved) in self.items.iter() {
+            if !on_level(location) {
+                return Err(format!("The saved {} is off the level", saved.name))
+            }
+            level.items.push((*location, saved.restore()?));
+        }
+        if !level.monsters.first().map(|monster| monster.mtype == MonsterType::Player).unwrap_or(false) {
+            return Err("The saved level has no player".to_string())
+        }
+        Ok(level)
+    }
+}
+
+// Level files name items like their sprites
+impl SavedItem {
+    fn new(item: &Item) -> SavedItem {
+        SavedItem {
+            name: item.sprite.to_string(),
+            equipped: item.equipped,
+            charge: item.spell.as_ref().map(|spell| spell.caster.energy),
+        }
+    }
+
+    fn restore(&self) -> Result<Item, String> {
+        let mut item = Item::from_name(&self.name).ok_or_else(|| format!("Unknown item {} in the save", self.name))?;
+        item.equipped = self.equipped;
+        if let (Some(spell), Some(charge)) = (item.spell.as_mut(), self.charge) {
+            spell.caster.energy = charge;
+        }
+        Ok(item)
+    }
+}
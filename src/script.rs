@@ -0,0 +1,611 @@
+use std::collections::HashMap;
+
+use crate::spells::{Command, CompileError, Statement, Span};
+
+lalrpop_mod!(pub spellscript); // synthesized by LALRPOP
+
+// Registers the VM writes query results into, plus the return value and a scratch register
+const ENERGY_REG: usize = 4; // e
+const RESULT_REG: usize = 17; // r
+const SCRATCH_REG: usize = 22; // w
+const X_REG: usize = 23; // x
+const Y_REG: usize = 24; // y
+const RETURN_REG: usize = 25; // z
+const RESERVED: &[usize] = &[ENERGY_REG, RESULT_REG, SCRATCH_REG, X_REG, Y_REG, RETURN_REG];
+
+// Builtin arguments that don't fit in free registers are popped into these
+const ARG_REGS: &[usize] = &[SCRATCH_REG, X_REG, Y_REG];
+
+// Where a variable lives. Variables that don't fit in the registers get a slot on the stack,
+// counted up from where the function's stack starts.
+#[derive(Copy, Clone)]
+enum Var {
+    Reg(usize),
+    Slot(usize),
+}
+
+// `spell`s are entry points that take their arguments in registers a, b, c, ... like any
+// other spell; `fn`s are called from scripts with their arguments on the stack
+pub struct Function {
+    pub offset: usize,
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Block,
+    pub is_spell: bool,
+}
+
+// Statements with the start and end of their source text
+pub type Block = Vec<(usize, usize, Stmt)>;
+
+pub enum Stmt {
+    Let(usize, String, Expr),
+    Assign(usize, String, Expr),
+    If(Expr, Block, Block),
+    While(Expr, Block),
+    Return(usize, Option<Expr>),
+    Expr(Expr),
+}
+
+pub enum Expr {
+    Num(i32),
+    Var(usize, String),
+    Call(usize, String, Vec<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Copy, Clone)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+// Compiles a script into commands that can be linked like any other spellbook source
+pub fn compile(source: &str) -> Result<Vec<Statement>, CompileError> {
+    let functions = spellscript::ProgramParser::new().parse(source)
+        .map_err(|err| CompileError::from_parse_error(err, source))?;
+    // Spells take no arguments when called from a script
+    let mut arities = HashMap::new();
+    for function in functions.iter() {
+        let arity = if function.is_spell { 0 } else { function.params.len() };
+        if arities.insert(function.name.clone(), arity).is_some() {
+            return Err(error(function.offset, format!("{} is defined twice", function.name)))
+        }
+    }
+    let mut out = Vec::new();
+    for function in functions.iter() {
+        let mut gen = FunctionGen {
+            out: &mut out,
+            arities: &arities,
+            name: &function.name,
+            next_label: 0,
+            span: (function.offset, function.offset),
+            free: (0..26).rev().filter(|reg| !RESERVED.contains(reg)).collect(),
+            scopes: vec!(Vec::new()),
+            depth: 0,
+        };
+        gen.function(function)?;
+    }
+    Ok(out)
+}
+
+struct FunctionGen<'a> {
+    out: &'a mut Vec<Statement>,
+    arities: &'a HashMap<String, usize>,
+    name: &'a str,
+    next_label: usize,
+    span: Span, // of the statement being compiled
+    free: Vec<usize>, // lowest register last, so it's handed out first
+    scopes: Vec<Vec<(String, Var)>>, // innermost block last
+    depth: usize, // values this function has on the stack
+}
+
+impl<'a> FunctionGen<'a> {
+    fn function(&mut self, function: &Function) -> Result<(), CompileError> {
+        self.out.push((Some((function.name.clone(), function.is_spell)), Command::Noop, self.span));
+        if function.is_spell {
+            if function.params.len() > self.free.len() {
+                return Err(error(function.offset, format!("spell {} has too many parameters", function.name)))
+            }
+            for param in function.params.iter() {
+                let reg = self.alloc(function.offset)?;
+                self.bind_new(param, reg);
+            }
+        } else {
+            // Arguments were pushed in order, so the last one is on top. As many as fit are popped
+            // into registers, leaving one free for expressions, and the rest stay where they are.
+            self.depth = function.params.len();
+            let in_regs = function.params.len().min(self.free.len() - 1);
+            let on_stack = function.params.len() - in_regs;
+            let mut regs = Vec::new();
+            for _ in 0..in_regs {
+                regs.push(self.alloc(function.offset)?);
+            }
+            for reg in regs.iter().rev() {
+                self.emit(Command::Pop(*reg));
+            }
+            for (slot, param) in function.params[..on_stack].iter().enumerate() {
+                self.bind(param, Var::Slot(slot));
+            }
+            for (param, reg) in function.params[on_stack..].iter().zip(regs) {
+                self.bind(param, Var::Reg(reg));
+            }
+        }
+        self.block(&function.body)?;
+        self.ret();
+        Ok(())
+    }
+
+    fn block(&mut self, block: &Block) -> Result<(), CompileError> {
+        self.scopes.push(Vec::new());
+        let outer_span = self.span;
+        for (start, end, stmt) in block {
+            self.span = (*start, *end);
+            self.stmt(stmt)?;
+        }
+        self.span = outer_span;
+        // Slots were pushed in order and everything since has been popped again
+        for (_, var) in self.scopes.pop().unwrap().into_iter().rev() {
+            match var {
+                Var::Reg(reg) => self.release(reg),
+                Var::Slot(_) => self.emit(Command::Pop(SCRATCH_REG)),
+            }
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        match stmt {
+            Stmt::Let(_, name, expr) => {
+                let reg = self.expr(expr)?;
+                self.bind_new(name, reg);
+            },
+            Stmt::Assign(offset, name, expr) => {
+                let var = self.lookup(*offset, name)?;
+                let reg = self.expr(expr)?;
+                match var {
+                    Var::Reg(var) => self.emit(Command::Copy(reg, var)),
+                    Var::Slot(slot) => {
+                        let depth = self.slot_depth(slot);
+                        self.emit(Command::Poke(reg, depth));
+                    },
+                }
+                self.release(reg);
+            },
+            Stmt::If(condition, then, otherwise) => {
+                let else_label = self.label();
+                let end_label = self.label();
+                let reg = self.expr(condition)?;
+                self.branch_if_zero(reg, &else_label);
+                self.release(reg);
+                self.block(then)?;
+                self.emit(Command::JumpStr(end_label.clone()));
+                self.place(else_label);
+                self.block(otherwise)?;
+                self.place(end_label);
+            },
+            Stmt::While(condition, body) => {
+                let top_label = self.label();
+                let end_label = self.label();
+                self.place(top_label.clone());
+                let reg = self.expr(condition)?;
+                self.branch_if_zero(reg, &end_label);
+                self.release(reg);
+                self.block(body)?;
+                self.emit(Command::JumpStr(top_label));
+                self.place(end_label);
+            },
+            Stmt::Return(_, expr) => {
+                if let Some(expr) = expr {
+                    let reg = self.expr(expr)?;
+                    self.emit(Command::Copy(reg, RETURN_REG));
+                    self.release(reg);
+                }
+                self.ret();
+            },
+            Stmt::Expr(expr) => {
+                let reg = self.expr(expr)?;
+                self.release(reg);
+            },
+        }
+        Ok(())
+    }
+
+    // Evaluates into a newly allocated register. There is always at least one free register
+    // when this is called: binary operators spill their left side to the stack when needed.
+    fn expr(&mut self, expr: &Expr) -> Result<usize, CompileError> {
+        Ok(match expr {
+            Expr::Num(value) => {
+                let reg = self.alloc(0)?;
+                self.emit(Command::Store(*value, reg));
+                reg
+            },
+            Expr::Var(offset, name) => {
+                let var = self.lookup(*offset, name)?;
+                let reg = self.alloc(*offset)?;
+                match var {
+                    Var::Reg(var) => self.emit(Command::Copy(var, reg)),
+                    Var::Slot(slot) => {
+                        let depth = self.slot_depth(slot);
+                        self.emit(Command::Peek(depth, reg));
+                    },
+                }
+                reg
+            },
+            Expr::Call(offset, name, args) => self.call(*offset, name, args)?,
+            Expr::Binary(op, left, right) => {
+                let left = self.expr(left)?;
+                if self.free.is_empty() {
+                    self.emit(Command::PushReg(left));
+                    self.release(left);
+                    let right = self.expr(right)?;
+                    self.emit(Command::Pop(SCRATCH_REG));
+                    self.binary(*op, SCRATCH_REG, right, right);
+                    right
+                } else {
+                    let right = self.expr(right)?;
+                    self.binary(*op, left, right, left);
+                    self.release(right);
+                    left
+                }
+            },
+            // Short circuiting: the right side is only evaluated if it decides the result
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                let is_and = match expr { Expr::And(_, _) => true, _ => false };
+                let short_label = self.label();
+                let end_label = self.label();
+                let left = self.expr(left)?;
+                if is_and { self.branch_if_zero(left, &short_label) } else { self.branch_if_nonzero(left, &short_label) }
+                self.release(left);
+                let right = self.expr(right)?;
+                if is_and { self.branch_if_zero(right, &short_label) } else { self.branch_if_nonzero(right, &short_label) }
+                self.emit(Command::Store(if is_and { 1 } else { 0 }, right));
+                self.emit(Command::JumpStr(end_label.clone()));
+                self.place(short_label);
+                self.emit(Command::Store(if is_and { 0 } else { 1 }, right));
+                self.place(end_label);
+                right
+            },
+            Expr::Neg(inner) => if let Expr::Num(value) = **inner {
+                let reg = self.alloc(0)?;
+                self.emit(Command::Store(-value, reg));
+                reg
+            } else {
+                let reg = self.expr(inner)?;
+                self.emit(Command::Store(0, SCRATCH_REG));
+                self.emit(Command::Sub(SCRATCH_REG, reg, reg));
+                reg
+            },
+            Expr::Not(inner) => {
+                let reg = self.expr(inner)?;
+                let zero_label = self.label();
+                let end_label = self.label();
+                self.branch_if_zero(reg, &zero_label);
+                self.emit(Command::Store(0, reg));
+                self.emit(Command::JumpStr(end_label.clone()));
+                self.place(zero_label);
+                self.emit(Command::Store(1, reg));
+                self.place(end_label);
+                reg
+            },
+        })
+    }
+
+    // `dest` is one of the operands, so comparisons read both before writing
+    fn binary(&mut self, op: BinOp, a: usize, b: usize, dest: usize) {
+        let (greater, less, swap_result) = match op {
+            BinOp::Add => return self.emit(Command::Add(a, b, dest)),
+            BinOp::Sub => return self.emit(Command::Sub(a, b, dest)),
+            BinOp::Mul => return self.emit(Command::Mul(a, b, dest)),
+            BinOp::Div => return self.emit(Command::Div(a, b, dest)),
+            BinOp::Mod => return self.emit(Command::Mod(a, b, dest)),
+            // Everything else is built from jump_if_gt: (first, second) is true when first > second
+            BinOp::Gt => (a, b, false),
+            BinOp::Lt => (b, a, false),
+            BinOp::Le => (a, b, true),
+            BinOp::Ge => (b, a, true),
+            BinOp::Eq | BinOp::Ne => {
+                let differ_label = self.label();
+                let end_label = self.label();
+                self.emit(Command::JumpIfGtStr(a, b, differ_label.clone()));
+                self.emit(Command::JumpIfGtStr(b, a, differ_label.clone()));
+                self.emit(Command::Store(if let BinOp::Eq = op { 1 } else { 0 }, dest));
+                self.emit(Command::JumpStr(end_label.clone()));
+                self.place(differ_label);
+                self.emit(Command::Store(if let BinOp::Eq = op { 0 } else { 1 }, dest));
+                self.place(end_label);
+                return
+            },
+        };
+        let true_label = self.label();
+        let end_label = self.label();
+        self.emit(Command::JumpIfGtStr(greater, less, true_label.clone()));
+        self.emit(Command::Store(if swap_result { 1 } else { 0 }, dest));
+        self.emit(Command::JumpStr(end_label.clone()));
+        self.place(true_label);
+        self.emit(Command::Store(if swap_result { 0 } else { 1 }, dest));
+        self.place(end_label);
+    }
+
+    fn call(&mut self, offset: usize, name: &str, args: &[Expr]) -> Result<usize, CompileError> {
+        if let Some(result) = self.builtin(offset, name, args)? {
+            return Ok(result)
+        }
+        match self.arities.get(name) {
+            Some(arity) if *arity != args.len() => {
+                return Err(error(offset, format!("{} takes {} arguments but was given {}", name, arity, args.len())))
+            },
+            // Anything else called without arguments is a spell or label, maybe from another file
+            None if !args.is_empty() => return Err(error(offset, format!("Unknown function {}", name))),
+            _ => (),
+        }
+        // The callee can use any register, so save everything that's live
+        let live = self.live();
+        for reg in live.iter() {
+            self.emit(Command::PushReg(*reg));
+        }
+        for arg in args {
+            let reg = self.expr(arg)?;
+            self.emit(Command::PushReg(reg));
+            self.release(reg);
+        }
+        self.emit(Command::CallStr(name.to_string()));
+        // The callee pops its own arguments
+        self.depth -= args.len();
+        for reg in live.iter().rev() {
+            self.emit(Command::Pop(*reg));
+        }
+        let reg = self.alloc(offset)?;
+        self.emit(Command::Copy(RETURN_REG, reg));
+        Ok(reg)
+    }
+
+    // System calls and queries; None if `name` isn't a builtin
+    fn builtin(&mut self, offset: usize, name: &str, args: &[Expr]) -> Result<Option<usize>, CompileError> {
+        let arity = match name {
+            "pickup" | "open" | "close" | "dig" | "build_wall" | "ignite" | "energy" | "self_x" | "self_y" | "cursor_x"
+                | "cursor_y" => 0,
+            "move" | "move_cursor" | "damage" | "drop" | "use_item" => 1,
+            "damage_radius" | "is_valid" | "is_passable" | "is_monster" | "tile_kind" => 2,
+            "damage_line" | "damage_cone" => 3,
+            _ => return Ok(None),
+        };
+        if args.len() != arity {
+            return Err(error(offset, format!("{} takes {} arguments but was given {}", name, arity, args.len())))
+        }
+        let regs = self.args(args)?;
+        let (command, result) = match name {
+            "pickup" => (Command::PickUp, None),
+            "open" => (Command::OpenDoor, None),
+            "close" => (Command::CloseDoor, None),
+            "dig" => (Command::Dig, None),
+            "build_wall" => (Command::BuildWall, None),
+            "ignite" => (Command::Ignite, None),
+            "energy" => (Command::QueryEnergy, Some(ENERGY_REG)),
+            "self_x" => (Command::QueryLocationSelf, Some(X_REG)),
+            "self_y" => (Command::QueryLocationSelf, Some(Y_REG)),
+            "cursor_x" => (Command::QueryLocationCursor, Some(X_REG)),
+            "cursor_y" => (Command::QueryLocationCursor, Some(Y_REG)),
+            "move" => (Command::Move(regs[0]), None),
+            "move_cursor" => (Command::MoveCursor(regs[0]), None),
+            "damage" => (Command::Damage(regs[0]), None),
+            "drop" => (Command::Drop(regs[0]), None),
+            "use_item" => (Command::UseItem(regs[0]), None),
+            "damage_radius" => (Command::DamageRadius(regs[0], regs[1]), None),
+            "is_valid" => (Command::QueryValidLocation(regs[0], regs[1]), Some(RESULT_REG)),
+            "is_passable" => (Command::QueryPassableLocation(regs[0], regs[1]), Some(RESULT_REG)),
+            "is_monster" => (Command::QueryMonsterLocation(regs[0], regs[1]), Some(RESULT_REG)),
+            "tile_kind" => (Command::QueryTileKind(regs[0], regs[1]), Some(RESULT_REG)),
+            "damage_line" => (Command::DamageLine(regs[0], regs[1], regs[2]), None),
+            "damage_cone" => (Command::DamageCone(regs[0], regs[1], regs[2]), None),
+            _ => unreachable!(),
+        };
+        self.emit(command);
+        for reg in regs.iter().filter(|reg| !ARG_REGS.contains(reg)) {
+            self.release(*reg);
+        }
+        let reg = self.alloc(offset)?;
+        match result {
+            Some(result) => self.emit(Command::Copy(result, reg)),
+            None => self.emit(Command::Store(0, reg)),
+        }
+        Ok(Some(reg))
+    }
+
+    // Keeps builtin arguments in free registers if there are enough, otherwise goes through
+    // the stack into the argument registers
+    fn args(&mut self, args: &[Expr]) -> Result<Vec<usize>, CompileError> {
+        let mut regs = Vec::new();
+        if args.len() <= self.free.len() {
+            for arg in args {
+                regs.push(self.expr(arg)?);
+            }
+        } else {
+            for arg in args {
+                let reg = self.expr(arg)?;
+                self.emit(Command::PushReg(reg));
+                self.release(reg);
+            }
+            regs.extend_from_slice(&ARG_REGS[..args.len()]);
+            for reg in regs.iter().rev() {
+                self.emit(Command::Pop(*reg));
+            }
+        }
+        Ok(regs)
+    }
+
+    fn branch_if_zero(&mut self, reg: usize, label: &str) {
+        let nonzero_label = self.label();
+        self.branch_if_nonzero(reg, &nonzero_label);
+        self.emit(Command::JumpStr(label.to_string()));
+        self.place(nonzero_label);
+    }
+
+    fn branch_if_nonzero(&mut self, reg: usize, label: &str) {
+        self.emit(Command::Store(0, SCRATCH_REG));
+        self.emit(Command::JumpIfGtStr(reg, SCRATCH_REG, label.to_string()));
+        self.emit(Command::JumpIfGtStr(SCRATCH_REG, reg, label.to_string()));
+    }
+
+    fn alloc(&mut self, offset: usize) -> Result<usize, CompileError> {
+        self.free.pop().ok_or_else(|| error(offset, format!("{} needs more registers than there are", self.name)))
+    }
+
+    fn release(&mut self, reg: usize) {
+        self.free.push(reg);
+        self.free.sort_by(|a, b| b.cmp(a));
+    }
+
+    fn live(&self) -> Vec<usize> {
+        (0..26).filter(|reg| !RESERVED.contains(reg) && !self.free.contains(reg)).collect()
+    }
+
+    fn bind(&mut self, name: &str, var: Var) {
+        self.scopes.last_mut().unwrap().push((name.to_string(), var));
+    }
+
+    // Moves the new variable's value onto the stack if it has the last free register
+    fn bind_new(&mut self, name: &str, reg: usize) {
+        if self.free.is_empty() {
+            self.emit(Command::PushReg(reg));
+            self.release(reg);
+            let slot = self.depth - 1;
+            self.bind(name, Var::Slot(slot));
+        } else {
+            self.bind(name, Var::Reg(reg));
+        }
+    }
+
+    // How far below the top of the stack a slot is right now
+    fn slot_depth(&self, slot: usize) -> usize {
+        self.depth - 1 - slot
+    }
+
+    fn lookup(&self, offset: usize, name: &str) -> Result<Var, CompileError> {
+        self.scopes.iter().rev()
+            .filter_map(|scope| scope.iter().rev().find(|(var, _)| var == name).map(|(_, reg)| *reg))
+            .next()
+            .ok_or_else(|| error(offset, format!("Unknown variable {}", name)))
+    }
+
+    // Generated labels start with an underscore so they can't clash with hand written ones
+    fn label(&mut self) -> String {
+        self.next_label += 1;
+        format!("_{}_{}", self.name, self.next_label)
+    }
+
+    fn place(&mut self, label: String) {
+        self.out.push((Some((label, false)), Command::Noop, self.span));
+    }
+
+    // Returning leaves the stack the way the caller had it. Code after a return statement is
+    // compiled as though it was still all there.
+    fn ret(&mut self) {
+        let depth = self.depth;
+        for _ in 0..depth {
+            self.emit(Command::Pop(SCRATCH_REG));
+        }
+        self.emit(Command::Return);
+        self.depth = depth;
+    }
+
+    fn emit(&mut self, command: Command) {
+        match command {
+            Command::PushReg(_) => self.depth += 1,
+            Command::Pop(_) => self.depth -= 1,
+            _ => (),
+        }
+        self.out.push((None, command, self.span));
+    }
+}
+
+fn error(offset: usize, message: String) -> CompileError {
+    CompileError {
+        offset: Some(offset),
+        message: message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::level::Level;
+    use crate::spells::{Spellbook, SpellEngine, CasterRef};
+    use super::compile;
+
+    const MAP: &str = "map\n.....\n..@..\n.....\n";
+
+    // Runs `spell` from the script and says where the player ended up, starting from (2, 1)
+    fn player_after(source: &str, spell: &str) -> (usize, usize) {
+        let spellbook = Spellbook::compile_script(source).unwrap();
+        let mut engine = SpellEngine::new(Level::parse(MAP).unwrap());
+        engine.do_turn(spell, &spellbook, &Spellbook::monster_spellbook());
+        engine.level.location(&CasterRef::Player)
+    }
+
+    #[test]
+    fn oversized_literal_is_an_error() {
+        let err = compile("spell big() { let a = 99999999999; }").err().unwrap();
+        assert_eq!(err.message, "Invalid number 99999999999");
+        assert_eq!(err.offset, Some(22));
+        assert!(compile("spell big() { let a = 2147483647; }").is_ok());
+    }
+
+    #[test]
+    fn unknown_variable_is_an_error() {
+        let err = compile("spell oops() { move(b); }").err().unwrap();
+        assert_eq!(err.message, "Unknown variable b");
+    }
+
+    #[test]
+    fn variables_spill_to_the_stack() {
+        // More variables than registers, read and assigned after they've spilled
+        let mut source = "spell many() {\n".to_string();
+        for i in 0..30 {
+            source.push_str(&format!("    let v{} = {};\n", i, i));
+        }
+        source.push_str("    v29 = v29 - v0 - 28;\n    v3 = v29 + v1;\n    move(v3 * 0 + #right + v2 - 2);\n}\n");
+        assert_eq!(player_after(&source, "many"), (3, 1));
+    }
+
+    #[test]
+    fn parameters_spill_to_the_stack() {
+        let mut params = Vec::new();
+        let mut args = Vec::new();
+        for i in 0..25 {
+            params.push(format!("p{}", i));
+            args.push(if i == 24 { "#down".to_string() } else { "0".to_string() });
+        }
+        let source = format!(
+            "fn pick({}) {{ let extra = p0; return p24 + extra; }}\nspell go() {{ move(pick({})); }}",
+            params.join(", "), args.join(", "));
+        assert_eq!(player_after(&source, "go"), (2, 2));
+    }
+
+    #[test]
+    fn spilled_blocks_leave_the_stack_balanced() {
+        let mut source = "fn inner() {\n".to_string();
+        for i in 0..25 {
+            source.push_str(&format!("    let v{} = 1;\n", i));
+        }
+        source.push_str("    if v24 { return #left; }\n    return 0;\n}\nspell outer() {\n");
+        // The last of these is on the stack, and reading it finds the wrong slot if inner left
+        // anything behind
+        for i in 0..20 {
+            source.push_str(&format!("    let w{} = {};\n", i, i));
+        }
+        source.push_str("    let d = inner();\n    move(d + w19 - 19);\n}\n");
+        assert_eq!(player_after(&source, "outer"), (1, 1));
+    }
+}
@@ -1,13 +1,26 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::ops::DerefMut;
 
 use crate::level::Level;
+use crate::tile::{Tile, TileKind, Hazard, Alteration};
 use crate::utils::{AbsoluteLocation, RelativeLocation, Direction};
-use crate::monster::Monster;
+use crate::monster::{Monster, Condition};
+use crate::events::GameEvent;
+use crate::item::{ItemKind, ITEM_SPELL_ENTRY};
+use crate::macros::CompileEnv;
+use crate::lexer::Lexer;
+use crate::script;
+use crate::journal::{Journal, Effect, RollbackPolicy};
+use crate::analysis::{self, CostEstimate};
 use constants::MONSTER_SPELLBOOK;
 
 lalrpop_mod!(pub cmdlist); // synthesized by LALRPOP
 
+use lalrpop_util::ParseError;
+
+#[derive(Clone)]
 pub struct Caster {
     pub location: AbsoluteLocation,
     pub energy: u32,
@@ -25,6 +38,16 @@ impl Caster {
         }
     }
 
+    // Energy that never regenerates, like the charge in a wand
+    pub fn charged(location: AbsoluteLocation, energy: u32) -> Caster {
+        Caster {
+            location: location,
+            energy: energy,
+            max_energy: energy,
+            energy_regen: 0,
+        }
+    }
+
     pub fn cast(&mut self, cost: u32) -> bool { // false if not enough energy
         if cost > self.energy { false } 
         else {
@@ -35,57 +58,290 @@ impl Caster {
 
     pub fn move_to(&mut self, location: AbsoluteLocation) { self.location = location }
 
+    pub fn max_energy(&self) -> u32 { self.max_energy }
+
     pub fn regen(&mut self) {
-        self.energy += self.energy_regen;
+        let regen = self.energy_regen;
+        self.restore(regen);
+    }
+
+    pub fn restore(&mut self, energy: u32) {
+        self.energy += energy;
         if self.energy > self.max_energy {
             self.energy = self.max_energy;
         }
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum CasterRef {
     Player,
     Monster(usize),
-    // TODO magical entity, item, etc.
+    Item(usize, usize), // monster holding the item, inventory slot
+    // TODO magical entity, etc.
+}
+
+// Byte offsets of the source text a command came from
+pub type Span = (usize, usize);
+
+// A command, its label if it has one (true for spells) and where it came from
+pub type Statement = (Option<(String, bool)>, Command, Span);
+
+pub struct CompileError {
+    pub offset: Option<usize>, // byte offset into the source, if known
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn from_parse_error<T: fmt::Display>(err: ParseError<usize, T, CompileError>, source: &str) -> CompileError {
+        let offset = match &err {
+            ParseError::InvalidToken { location } => *location,
+            ParseError::UnrecognizedToken { token: Some((start, _, _)), .. } => *start,
+            ParseError::UnrecognizedToken { token: None, .. } => source.len(),
+            ParseError::ExtraToken { token: (start, _, _) } => *start,
+            ParseError::User { .. } => 0,
+        };
+        match err {
+            ParseError::User { error } => error,
+            err => CompileError {
+                offset: Some(offset),
+                message: format!("{}", err),
+            },
+        }
+    }
+
+    // Zero-based line and column of the error
+    pub fn line_col(&self, source: &str) -> Option<(usize, usize)> {
+        self.offset.map(|offset| {
+            let before = &source[..offset.min(source.len())];
+            let line = before.matches('\n').count();
+            let col = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+            (line, col)
+        })
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} at {:?}", self.message, self.offset)
+    }
 }
 
 pub struct Spellbook {
     commands: Vec<Command>,
+    spans: Vec<Span>,
     spell_table: HashMap<String, usize>,
+    label_table: HashMap<String, usize>, // every label, spells included
+    costs: HashMap<String, CostEstimate>,
+    warnings: Vec<(usize, String)>, // address, message
 }
 
 impl Spellbook {
     pub fn monster_spellbook() -> Spellbook {
-        let list: Vec<(Option<(&str, bool)>, Command)> = cmdlist::CmdListParser::new().parse(MONSTER_SPELLBOOK).unwrap();
-        let (labels, commands): (Vec<_>, Vec<_>) = list.into_iter().unzip();
+        Spellbook::compile(MONSTER_SPELLBOOK).expect("monster spellbook should compile")
+    }
+
+    // Includes are only available through `compile_with`
+    pub fn compile(source: &str) -> Result<Spellbook, CompileError> {
+        Spellbook::compile_with(source, Box::new(|path: &str| Err(format!("cannot include {} here", path))))
+    }
+
+    // `include` loads the named file through the given function
+    pub fn compile_with(source: &str, include: Box<dyn Fn(&str) -> Result<String, String>>) -> Result<Spellbook, CompileError> {
+        let env = RefCell::new(CompileEnv::new(include));
+        let list: Vec<Statement> = cmdlist::CmdListParser::new().parse(&env, Lexer::new(source))
+            .map_err(|err| CompileError::from_parse_error(err, source))?;
+        Spellbook::link(list)
+    }
+
+    // Scripts in the higher level language, see `script`
+    pub fn compile_script(source: &str) -> Result<Spellbook, CompileError> {
+        Spellbook::link(script::compile(source)?)
+    }
+
+    // Resolves label references into addresses
+    fn link(list: Vec<Statement>) -> Result<Spellbook, CompileError> {
         let mut spell_table: HashMap<String, usize> = HashMap::new();
         let mut label_table: HashMap<String, usize> = HashMap::new();
-        for (i, label) in labels.into_iter().enumerate() {
+        for (i, (label, _, span)) in list.iter().enumerate() {
             if let Some((label, is_spell)) = label {
-                if is_spell {
-                    spell_table.insert(label.to_string(), i);
+                if label_table.insert(label.clone(), i).is_some() {
+                    return Err(CompileError {
+                        offset: Some(span.0),
+                        message: format!("label {} is defined twice", label),
+                    })
+                }
+                if *is_spell {
+                    spell_table.insert(label.clone(), i);
                 }
-                label_table.insert(label.to_string(), i);
             }
         }
-        let commands = commands.into_iter().map(|cmd| match cmd {
-            // TODO handle calls/jump to invalid labels
-            Command::CallStr(label) => Command::Call(*label_table.get(&label).unwrap()),
-            Command::JumpStr(label) => Command::Jump(*label_table.get(&label).unwrap()),
-            Command::JumpIfGtStr(a, b, label) => Command::JumpIfGt(a, b, *label_table.get(&label).unwrap()),
-            _ => cmd,
-        }).collect();
-        Spellbook {
+        let mut commands = Vec::new();
+        let mut spans = Vec::new();
+        for (_, cmd, span) in list {
+            let resolve = |label: &String| label_table.get(label).cloned().ok_or(CompileError {
+                offset: Some(span.0),
+                message: format!("Unknown label {}", label),
+            });
+            commands.push(match cmd {
+                Command::CallStr(label) => Command::Call(resolve(&label)?),
+                Command::JumpStr(label) => Command::Jump(resolve(&label)?),
+                Command::JumpIfGtStr(a, b, label) => Command::JumpIfGt(a, b, resolve(&label)?),
+                _ => cmd,
+            });
+            spans.push(span);
+        }
+        let mut spellbook = Spellbook {
             commands: commands,
+            spans: spans,
             spell_table: spell_table,
+            label_table: label_table,
+            costs: HashMap::new(),
+            warnings: Vec::new(),
+        };
+        spellbook.optimize();
+        spellbook.analyze();
+        Ok(spellbook)
+    }
+
+    // Folds constants and drops noops and code that can't run, see `analysis`
+    fn optimize(&mut self) {
+        let entries: Vec<usize> = self.spell_table.values().cloned().collect();
+        analysis::fold_constants(&mut self.commands, &entries);
+        let keep = analysis::live(&self.commands, &entries);
+        let reachable = analysis::reachable(&self.commands, &entries);
+        // A dropped command's address goes to the next command kept
+        let mut remap = Vec::with_capacity(keep.len() + 1);
+        let mut next = 0;
+        for kept in keep.iter() {
+            remap.push(next);
+            if *kept {
+                next += 1;
+            }
+        }
+        remap.push(next);
+        let moved = |address: &usize| remap.get(*address).cloned().unwrap_or(next);
+        let mut commands = Vec::with_capacity(next);
+        let mut spans = Vec::with_capacity(next);
+        for ((cmd, span), kept) in self.commands.drain(..).zip(self.spans.drain(..)).zip(keep) {
+            if kept {
+                commands.push(match cmd {
+                    Command::Call(dest) => Command::Call(moved(&dest)),
+                    Command::Jump(dest) => Command::Jump(moved(&dest)),
+                    Command::JumpIfGt(a, b, dest) => Command::JumpIfGt(a, b, moved(&dest)),
+                    _ => cmd,
+                });
+                spans.push(span);
+            }
+        }
+        self.commands = commands;
+        self.spans = spans;
+        // Labels on code that can't run would otherwise point at whatever comes next
+        let spells = &self.spell_table;
+        self.label_table.retain(|label, address| reachable[*address] || spells.contains_key(label));
+        for address in self.spell_table.values_mut().chain(self.label_table.values_mut()) {
+            *address = moved(address);
+        }
+    }
+
+    // Energy estimates for every spell, and warnings about registers and the stack
+    fn analyze(&mut self) {
+        let mut spells: Vec<(&String, &usize)> = self.spell_table.iter().collect();
+        spells.sort();
+        let mut underflows = HashSet::new();
+        for (spell, entry) in spells {
+            let (cost, underflow_at) = analysis::estimate_cost(&self.commands, *entry);
+            self.costs.insert(spell.clone(), cost);
+            for address in underflow_at {
+                if underflows.insert(address) {
+                    self.warnings.push((address, format!("spell {} can run out of stack here", spell)));
+                }
+            }
+            for (address, reg) in analysis::unwritten_reads(&self.commands, *entry) {
+                let message = format!("spell {} reads register {} without setting it, so it only holds an argument", spell, (b'a' + reg as u8) as char);
+                self.warnings.push((address, message));
+            }
+        }
+        let entries: Vec<usize> = self.spell_table.values().cloned().collect();
+        for address in analysis::zero_divisors(&self.commands, &entries) {
+            self.warnings.push((address, "division by zero here always fails the spell".to_string()));
         }
     }
+
+    pub fn cost(&self, spell: &str) -> Option<CostEstimate> {
+        self.costs.get(spell).cloned()
+    }
+
+    // Sorted by where in the source they were found
+    pub fn warnings(&self) -> Vec<(Span, String)> {
+        let mut warnings: Vec<(Span, String)> = self.warnings.iter()
+            .map(|(address, message)| (self.spans[*address], message.clone()))
+            .collect();
+        warnings.sort();
+        warnings
+    }
+
+    pub fn has_spell(&self, label: &str) -> bool {
+        self.spell_table.contains_key(label)
+    }
+
+    pub fn spell_names(&self) -> Vec<String> {
+        self.spell_table.keys().cloned().collect()
+    }
+
+    // Where in the source the command at `address` came from
+    pub fn span(&self, address: usize) -> Option<Span> {
+        self.spans.get(address).cloned()
+    }
+
+    // One line per resolved command with its source line, and the labels that point at each
+    // address. `source` is what the spellbook was compiled from.
+    pub fn disassemble(&self, source: &str) -> Vec<String> {
+        let mut labels_at: HashMap<usize, Vec<&str>> = HashMap::new();
+        for (label, address) in self.label_table.iter() {
+            labels_at.entry(*address).or_insert(Vec::new()).push(label);
+        }
+        for labels in labels_at.values_mut() {
+            labels.sort();
+        }
+        let mut lines = Vec::new();
+        for (address, cmd) in self.commands.iter().enumerate() {
+            for label in labels_at.get(&address).map(|labels| labels.as_slice()).unwrap_or(&[]) {
+                if self.spell_table.contains_key(*label) {
+                    lines.push(format!(":{}:", label));
+                } else {
+                    lines.push(format!("{}:", label));
+                }
+            }
+            let target = match cmd {
+                Command::Call(dest) | Command::Jump(dest) | Command::JumpIfGt(_, _, dest) => {
+                    match labels_at.get(dest) {
+                        Some(labels) => format!("  ; -> {}", labels.join(", ")),
+                        None => "  ; -> ?".to_string(),
+                    }
+                },
+                _ => String::new(),
+            };
+            let line = source[..self.spans[address].0.min(source.len())].matches('\n').count() + 1;
+            lines.push(format!("{:5} {:5}  {:?}{}", address, line, cmd, target));
+        }
+        lines
+    }
 }
 
+#[derive(Debug, PartialEq)]
 pub enum Command {
     PushVal(i32), 
     PushReg(usize),
     Pop(usize),
+    Peek(usize, usize), // slots below the top of the stack, destination register
+    Poke(usize, usize), // source register, slots below the top of the stack
     Copy(usize, usize),
     Store(i32, usize),
     Call(usize),
@@ -115,6 +371,18 @@ pub enum Command {
     MoveCursor(usize),
     Damage(usize), // energy
     Move(usize),
+    PickUp,
+    Drop(usize), // inventory slot
+    UseItem(usize), // inventory slot
+    DamageRadius(usize, usize), // energy per tile, radius around the cursor
+    DamageLine(usize, usize, usize), // energy per tile, direction, length from the cursor
+    DamageCone(usize, usize, usize), // energy per tile, direction, length from the cursor
+    // Terrain changes at the cursor, priced by what's there
+    OpenDoor,
+    CloseDoor,
+    Dig,
+    BuildWall,
+    Ignite,
     //Conjure(usize, i32), // spell label, energy -> result in c
     //Launch(usize, usize, usize), // object, x, y
 
@@ -125,6 +393,7 @@ pub enum Command {
     QueryValidLocation(usize, usize), // result r (bool)
     QueryPassableLocation(usize, usize), // result r (bool)
     QueryMonsterLocation(usize, usize), // result r (bool)
+    QueryTileKind(usize, usize), // result r, see `tile::KIND_NAMES`
 }
 
 pub struct SpellEngine {
@@ -132,6 +401,13 @@ pub struct SpellEngine {
     stack: Vec<i32>,
     call_stack: Vec<usize>,
     pub level: Level,
+    events: Vec<GameEvent>,
+    pub turn: u32,
+    pending_zaps: Vec<(usize, usize, [i32; 26])>, // holder, slot, registers of the spell that used the item
+    pub rollback: RollbackPolicy,
+    pub journal: Journal, // what this turn's spells did to the level
+    out_of_energy: bool,
+    acting: (usize, bool), // where the monster taking its turn is in the list, and whether it's alive
 }
 
 const STACK_SIZE: usize = 1000;
@@ -143,36 +419,73 @@ impl SpellEngine {
             stack: Vec::with_capacity(STACK_SIZE),
             call_stack: Vec::with_capacity(STACK_SIZE),
             level: level,
+            events: Vec::new(),
+            turn: 0,
+            pending_zaps: Vec::new(),
+            rollback: RollbackPolicy::UndoFailed,
+            journal: Journal::new(),
+            out_of_energy: false,
+            acting: (0, true),
         }
     }
 
-    pub fn do_turn(&mut self, spell: &str, player_spellbook: &Spellbook, monster_spellbook: &Spellbook) -> Option<String> {
-        let player_error = self.cast(&CasterRef::Player, player_spellbook, spell);
-        if player_error.is_some() {
-            player_error
-        } else {
+    pub fn do_turn(&mut self, spell: &str, player_spellbook: &Spellbook, monster_spellbook: &Spellbook) -> Vec<GameEvent> {
+        self.do_turn_with_args(spell, &[], player_spellbook, monster_spellbook)
+    }
+
+    // Arguments are passed to the player's spell in registers a, b, c, ...
+    // If the player's spell fails the events end with a `SpellFailed` and the turn doesn't pass.
+    // The turn ends as soon as the player dies, and after that nothing more happens.
+    pub fn do_turn_with_args(&mut self, spell: &str, args: &[i32], player_spellbook: &Spellbook, monster_spellbook: &Spellbook) -> Vec<GameEvent> {
+        if self.level.monsters[0].is_dead() {
+            return vec!(GameEvent::SpellFailed(CasterRef::Player, spell.to_string(), "You are dead".to_string()))
+        }
+        self.journal.clear();
+        for (i, arg) in args.iter().take(26).enumerate() {
+            self.registers[i] = *arg;
+        }
+        let from = self.level.location(&CasterRef::Player);
+        self.acting = (0, true);
+        if self.cast_with_items(&CasterRef::Player, player_spellbook, spell).is_none() {
+            self.turn += 1;
             self.level.regen(&CasterRef::Player);
-            // Do all other casters turns
-            (1..self.level.monsters.len()).filter_map(|index| {
+            self.suffer_hazard(0, from);
+            // Do all other casters turns. Monsters that die move the ones after them down a place,
+            // so `acting` follows the one whose turn it is.
+            let mut index = 1;
+            while index < self.level.monsters.len() && !self.level.monsters[0].is_dead() {
+                self.acting = (index, true);
                 let caster_ref = CasterRef::Monster(index);
+                let from = self.level.location(&caster_ref);
                 let spell = self.level.get_spell(&caster_ref);
-                let result = self.cast(&caster_ref, monster_spellbook, spell);
-                if result.is_none() {
-                    self.level.regen(&caster_ref);
+                let succeeded = self.cast_with_items(&caster_ref, monster_spellbook, spell).is_none();
+                let (position, alive) = self.acting;
+                if alive {
+                    if succeeded {
+                        self.level.regen(&CasterRef::Monster(position));
+                    }
+                    self.suffer_hazard(position, from);
                 }
-                result
-            }).next()
+                let (position, alive) = self.acting;
+                index = if alive { position + 1 } else { position };
+            }
         }
+        self.events.drain(..).collect()
     }
 
     fn cast(&mut self, caster_ref: &CasterRef, spellbook: &Spellbook, spell: &str) -> Option<String> {
         if let Some(i) = spellbook.spell_table.get(spell) {
+            let mark = self.journal.mark();
+            let events = self.events.len();
+            let acting = self.acting;
+            self.out_of_energy = false;
             let mut result = None;
             let mut instruction_pointer = *i;
             let mut cursor = self.level.location(&caster_ref).clone();
             while result.is_none() {
                 if instruction_pointer >= spellbook.commands.len() {
-                    return Some("Unexpected end of execution".to_string())
+                    result = Some("Unexpected end of execution".to_string());
+                    break;
                 }
                 let cmd = &spellbook.commands[instruction_pointer];
                 instruction_pointer += 1;
@@ -195,6 +508,20 @@ impl SpellEngine {
                     } else {
                         Some("Called pop on empty stack".to_string())
                     },
+                    Command::Peek(depth, reg) => match self.stack.len().checked_sub(depth + 1) {
+                        Some(index) => {
+                            self.registers[*reg] = self.stack[index];
+                            None
+                        },
+                        None => Some("Called peek past the bottom of the stack".to_string()),
+                    },
+                    Command::Poke(reg, depth) => match self.stack.len().checked_sub(depth + 1) {
+                        Some(index) => {
+                            self.stack[index] = self.registers[*reg];
+                            None
+                        },
+                        None => Some("Called poke past the bottom of the stack".to_string()),
+                    },
                     Command::Copy(src, dest) => {
                         self.registers[*dest] = self.registers[*src];
                         None
@@ -224,33 +551,21 @@ impl SpellEngine {
                         Some("called jump to invalid location".to_string())
                     },
                     Command::JumpIfGt(a, b, dest) => if *dest < spellbook.commands.len() {
-                        if a > b {
+                        if self.registers[*a] > self.registers[*b] {
                             instruction_pointer = *dest;
                         }
                         None
                     } else {
                         Some("called jumpifgt to invalid location".to_string())
                     },
-                    Command::Add(a, b, dest) => {
-                        self.registers[*dest] = self.registers[*a] + self.registers[*b];
-                        None
-                    },
-                    Command::Mul(a, b, dest) => {
-                        self.registers[*dest] = self.registers[*a] * self.registers[*b];
-                        None
-                    },
-                    Command::Sub(a, b, dest) => {
-                        self.registers[*dest] = self.registers[*a] - self.registers[*b];
-                        None
-                    },
-                    Command::Div(a, b, dest) => {
-                        self.registers[*dest] = self.registers[*a] / self.registers[*b];
-                        None
-                    },
-                    Command::Mod(a, b, dest) => {
-                        self.registers[*dest] = self.registers[*a] % self.registers[*b];
-                        None
+                    Command::Add(a, b, dest) => self.arithmetic(*a, *b, *dest, i32::checked_add),
+                    Command::Mul(a, b, dest) => self.arithmetic(*a, *b, *dest, i32::checked_mul),
+                    Command::Sub(a, b, dest) => self.arithmetic(*a, *b, *dest, i32::checked_sub),
+                    Command::Div(_, b, _) | Command::Mod(_, b, _) if self.registers[*b] == 0 => {
+                        Some("Division by zero".to_string())
                     },
+                    Command::Div(a, b, dest) => self.arithmetic(*a, *b, *dest, i32::checked_div),
+                    Command::Mod(a, b, dest) => self.arithmetic(*a, *b, *dest, i32::checked_rem),
                     Command::And(a, b, dest) => {
                         self.registers[*dest] = self.registers[*a] & self.registers[*b];
                         None
@@ -271,9 +586,11 @@ impl SpellEngine {
                     Command::PromptLocation => Some("Prompting not yet supported".to_string()),
                     Command::MoveCursor(register) => {
                         if let Some(direction) = Direction::from_num(self.registers[*register]) {
-                            if let Some(loc) = self.level.reify_location(direction.location(), &self.level.location(&caster_ref)) {
+                            if self.level.cuts_corner(&self.level.location(&caster_ref), &direction) {
+                                Some("Can't reach around that corner".to_string())
+                            } else if let Some(loc) = self.level.reify_location(direction.location(), &self.level.location(&caster_ref)) {
                                 // TODO cursor move energy cost?
-                                if self.level.cast(&caster_ref, 5) {
+                                if self.spend(&caster_ref, 5) {
                                     cursor = loc;
                                     None
                                 } else {
@@ -291,8 +608,10 @@ impl SpellEngine {
                         let energy = self.registers[*register] as u32;
                         // TODO convert energy to damage
                         if self.level.is_monster(&cursor) {
-                            if self.level.cast(&caster_ref, energy) {
-                                self.level.damage(&cursor, energy);
+                            if self.spend(&caster_ref, energy) {
+                                self.events.push(GameEvent::Attacked(self.level.location(&caster_ref), cursor));
+                                let damage = energy + self.level.weapon_bonus(&caster_ref);
+                                self.damage_at(&caster_ref, &cursor, damage);
                                 None
                             } else {
                                 Some("Not enough energy to attack".to_string())
@@ -303,17 +622,22 @@ impl SpellEngine {
                     },
                     Command::Move(register) => {
                         if let Some(direction) = Direction::from_num(self.registers[*register]) {
-                            if let Some(loc) = self.level.reify_location(direction.location(), &self.level.location(&caster_ref)) {
+                            if self.level.cuts_corner(&self.level.location(&caster_ref), &direction) {
+                                Some("Can't squeeze past that corner".to_string())
+                            } else if let Some(loc) = self.level.reify_location(direction.location(), &self.level.location(&caster_ref)) {
                                 if self.level.is_passable(&loc) && !self.level.is_monster(&loc) {
-                                    if self.level.cast(&caster_ref, 10) {
+                                    if self.spend(&caster_ref, 10) {
                                         // TODO check if valid move
                                         // TODO multiply cost by distance moved or just check that it's
                                         // adjacent?
-                                        self.level.move_to(&caster_ref, loc);
+                                        self.move_to(&caster_ref, loc);
                                         None
                                     } else {
                                         Some("Not enough energy to move".to_string())
                                     }
+                                } else if self.level.tile(&loc).kind == TileKind::Door(false) {
+                                    // Walking into a closed door opens it instead
+                                    self.alter_terrain(&caster_ref, &loc, Alteration::Open)
                                 } else {
                                     Some("That space is occupied".to_string())
                                 }
@@ -324,6 +648,94 @@ impl SpellEngine {
                             Some("Invalid direction".to_string())
                         }
                     },
+                    // Item actions cost no energy, only the turn
+                    Command::PickUp => match self.item_action(&caster_ref, |level| level.pick_up(&caster_ref)) {
+                        Ok(name) => {
+                            self.events.push(GameEvent::PickedUp(name));
+                            None
+                        },
+                        Err(err) => Some(err),
+                    },
+                    Command::Drop(slot_reg) => match self.slot(*slot_reg).and_then(|slot| self.item_action(&caster_ref, |level| level.drop_item(&caster_ref, slot))) {
+                        Ok(name) => {
+                            self.events.push(GameEvent::Dropped(name));
+                            None
+                        },
+                        Err(err) => Some(err),
+                    },
+                    Command::UseItem(slot_reg) => match self.slot(*slot_reg) {
+                        Err(err) => Some(err),
+                        Ok(_) if match caster_ref { CasterRef::Item(_, _) => true, _ => false } => {
+                            Some("Items can't use other items".to_string())
+                        },
+                        Ok(slot) => {
+                            let enchanted = self.level.item(&caster_ref, slot)
+                                .and_then(|item| item.spell.as_ref().map(|_| (item.name.clone(), item.kind.clone())));
+                            if let Some((name, kind)) = enchanted {
+                                // The item's program runs once this spell finishes
+                                let holder = self.level.monster_index(&caster_ref);
+                                self.pending_zaps.push((holder, slot, self.registers));
+                                let verb = match kind { ItemKind::Scroll => "read", _ => "zap" };
+                                self.events.push(GameEvent::ItemUsed(format!("You {} the {}", verb, name)));
+                                None
+                            } else {
+                                let teaches = self.level.item(&caster_ref, slot)
+                                    .and_then(|item| item.teaches.clone().map(|label| (label, item.name.clone())));
+                                match self.item_action(&caster_ref, |level| level.use_item(&caster_ref, slot)) {
+                                    Ok(description) => {
+                                        self.events.push(GameEvent::ItemUsed(description));
+                                        if let Some((label, source)) = teaches {
+                                            if self.level.monster_index(&caster_ref) == 0 {
+                                                self.events.push(GameEvent::SpellRevealed(label, source));
+                                            }
+                                        }
+                                        None
+                                    },
+                                    Err(err) => Some(err),
+                                }
+                            }
+                        },
+                    },
+                    Command::DamageRadius(energy_reg, radius_reg) => {
+                        let radius = self.registers[*radius_reg];
+                        if radius < 0 {
+                            Some("Invalid radius".to_string())
+                        } else {
+                            let area = self.level.radius_area(&cursor, radius as usize);
+                            self.damage_area(&caster_ref, area, *energy_reg)
+                        }
+                    },
+                    Command::DamageLine(energy_reg, direction_reg, length_reg) => {
+                        let length = self.registers[*length_reg];
+                        if let Some(direction) = Direction::from_num(self.registers[*direction_reg]) {
+                            if length < 0 {
+                                Some("Invalid length".to_string())
+                            } else {
+                                let area = self.level.line_area(&cursor, &direction, length as usize);
+                                self.damage_area(&caster_ref, area, *energy_reg)
+                            }
+                        } else {
+                            Some("Invalid direction".to_string())
+                        }
+                    },
+                    Command::DamageCone(energy_reg, direction_reg, length_reg) => {
+                        let length = self.registers[*length_reg];
+                        if let Some(direction) = Direction::from_num(self.registers[*direction_reg]) {
+                            if length < 0 {
+                                Some("Invalid length".to_string())
+                            } else {
+                                let area = self.level.cone_area(&cursor, &direction, length as usize);
+                                self.damage_area(&caster_ref, area, *energy_reg)
+                            }
+                        } else {
+                            Some("Invalid direction".to_string())
+                        }
+                    },
+                    Command::OpenDoor => self.alter_terrain(&caster_ref, &cursor, Alteration::Open),
+                    Command::CloseDoor => self.alter_terrain(&caster_ref, &cursor, Alteration::Close),
+                    Command::Dig => self.alter_terrain(&caster_ref, &cursor, Alteration::Dig),
+                    Command::BuildWall => self.alter_terrain(&caster_ref, &cursor, Alteration::BuildWall),
+                    Command::Ignite => self.alter_terrain(&caster_ref, &cursor, Alteration::Ignite),
                     //Command::Conjure(_spell, _energy) => Some("conjuring not yet supported".to_string()),
                     //Command::Launch(_object, _x, _y) => Some("launching not yet supported".to_string()),
                     Command::QueryEnergy => {
@@ -371,19 +783,260 @@ impl SpellEngine {
                         }
                         None
                     },
+                    Command::QueryTileKind(x_reg, y_reg) => {
+                        let rel_loc = (self.registers[*x_reg] as isize, self.registers[*y_reg] as isize);
+                        self.registers[17] = match self.level.reify_location(rel_loc, &(0, 0)) {
+                            Some(loc) => self.level.tile(&loc).kind.code(),
+                            None => TileKind::Nothing.code(),
+                        };
+                        None
+                    },
                     // TODO handle this better
                     Command::CallStr(_) => panic!("Can't execute call str"),
                     Command::JumpStr(_) => panic!("Can't execute jump str"),
                     Command::JumpIfGtStr(_, _, _) => panic!("Can't execute jump str"),
                 }
             }
+            if result.is_some() && self.rollback.applies(self.out_of_energy) {
+                self.journal.rollback(mark, &mut self.level);
+                self.events.truncate(events);
+                self.acting = acting;
+                result = result.map(|error| format!("{}, so the spell unravels", error));
+            }
             self.clear();
+            if let Some(error) = result.as_ref() {
+                self.events.push(GameEvent::SpellFailed(*caster_ref, spell.to_string(), error.clone()));
+            }
             result
         } else {
-            Some("Unknown spell".to_string())
+            self.clear();
+            let error = "Unknown spell".to_string();
+            self.events.push(GameEvent::SpellFailed(*caster_ref, spell.to_string(), error.clone()));
+            Some(error)
+        }
+    }
+
+    // A spell whose items fail is undone along with them, like a spell that fails itself. When the
+    // rollback policy keeps the spell's effects, the spell still took its turn.
+    fn cast_with_items(&mut self, caster_ref: &CasterRef, spellbook: &Spellbook, spell: &str) -> Option<String> {
+        let mark = self.journal.mark();
+        let events = self.events.len();
+        let acting = self.acting;
+        match self.cast(caster_ref, spellbook, spell) {
+            None => match self.run_zaps() {
+                Some(error) if self.rollback.applies(self.out_of_energy) => {
+                    // Keeps the item's `SpellFailed`
+                    let failed = self.events.pop();
+                    self.journal.rollback(mark, &mut self.level);
+                    self.events.truncate(events);
+                    self.events.extend(failed);
+                    self.acting = acting;
+                    Some(error)
+                },
+                _ => None,
+            },
+            error => {
+                // Items used by a failed spell never go off
+                self.pending_zaps.clear();
+                error
+            },
+        }
+    }
+
+    // Casts the programs of any wands or scrolls used by the last spell
+    fn run_zaps(&mut self) -> Option<String> {
+        let mut zaps: Vec<(usize, usize, [i32; 26])> = self.pending_zaps.drain(..).collect();
+        // Highest slot first so used up scrolls don't shift the slots still waiting
+        zaps.sort_by(|a, b| b.1.cmp(&a.1));
+        for (holder, slot, registers) in zaps {
+            if let Some(spellbook) = self.level.item_spellbook(holder, slot) {
+                self.registers = registers;
+                let result = self.cast(&CasterRef::Item(holder, slot), &spellbook, ITEM_SPELL_ENTRY);
+                if result.is_some() {
+                    return result
+                }
+                // Scrolls are only used up by spells that work
+                if let Some(before) = self.level.monsters.get(holder).cloned() {
+                    if self.level.use_up_scroll(holder, slot) {
+                        self.journal.record(Effect::Items(holder, "used up a scroll".to_string(), before, self.level.items.clone()));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Overflow fails the spell
+    fn arithmetic(&mut self, a: usize, b: usize, dest: usize, op: fn(i32, i32) -> Option<i32>) -> Option<String> {
+        match op(self.registers[a], self.registers[b]) {
+            Some(value) => {
+                self.registers[dest] = value;
+                None
+            },
+            None => Some("Arithmetic overflow".to_string()),
+        }
+    }
+
+    fn slot(&self, register: usize) -> Result<usize, String> {
+        let slot = self.registers[register];
+        if slot < 0 {
+            Err("No such item".to_string())
+        } else {
+            Ok(slot as usize)
+        }
+    }
+
+    // The player learns the lore of any monster they defeat
+    fn damage_at(&mut self, caster_ref: &CasterRef, location: &AbsoluteLocation, damage: u32) {
+        let by_player = self.level.monster_index(caster_ref) == 0;
+        let target = match self.level.monsters.iter().position(|monster| monster.location() == *location) {
+            Some(index) => index,
+            None => return,
+        };
+        let before = self.level.monsters[target].clone();
+        let mtype = before.mtype;
+        let items = self.level.items.len();
+        let died = self.level.damage(location, damage);
+        let effect = match died {
+            Some(_) => Effect::Died(target, before, self.level.items.len() - items),
+            None => Effect::Damaged(target, damage, before.stats.current_hp),
+        };
+        self.journal.record(effect);
+        self.events.push(GameEvent::Damaged(mtype, *location, damage));
+        if let Some(dead) = died {
+            let (position, alive) = self.acting;
+            if target < position {
+                self.acting = (position - 1, alive);
+            } else if target == position {
+                self.acting = (position, false);
+            }
+            self.events.push(GameEvent::Died(mtype, *location));
+            if by_player {
+                for label in dead.lore.iter() {
+                    self.events.push(GameEvent::SpellRevealed(label.clone(), format!("defeated {}", dead.mtype.name())));
+                }
+            }
+        } else if self.level.monsters[target].is_dead() {
+            self.events.push(GameEvent::Died(mtype, *location));
+        }
+    }
+
+    // Costs energy for every tile covered, whether or not anyone is standing there
+    fn damage_area(&mut self, caster_ref: &CasterRef, area: Vec<AbsoluteLocation>, energy_reg: usize) -> Option<String> {
+        let energy = self.registers[energy_reg];
+        if energy < 0 {
+            return Some("Invalid energy".to_string())
+        }
+        let energy = energy as u32;
+        if area.is_empty() {
+            return Some("Nothing in range".to_string())
+        }
+        // Costs too big to count are more than anyone has
+        let paid = match energy.checked_mul(area.len() as u32) {
+            Some(cost) => self.spend(caster_ref, cost),
+            None => {
+                self.out_of_energy = true;
+                false
+            },
+        };
+        if paid {
+            // TODO convert energy to damage
+            let targets = self.level.area_targets(caster_ref, &area);
+            self.events.push(GameEvent::Blast(area));
+            for target in targets {
+                self.damage_at(caster_ref, &target, energy);
+            }
+            None
+        } else {
+            Some("Not enough energy for area spell".to_string())
+        }
+    }
+
+    // Every change a spell makes to the level goes through the journal so it can be undone
+    fn spend(&mut self, caster_ref: &CasterRef, cost: u32) -> bool {
+        let energy = self.level.get_energy(caster_ref);
+        let paid = self.level.cast(caster_ref, cost);
+        if paid {
+            self.journal.record(Effect::Spent(*caster_ref, cost, energy));
+            self.events.push(GameEvent::EnergySpent(*caster_ref, cost));
+        } else {
+            self.out_of_energy = true;
+        }
+        paid
+    }
+
+    fn move_to(&mut self, caster_ref: &CasterRef, location: AbsoluteLocation) {
+        let from = self.level.location(caster_ref);
+        let index = self.level.monster_index(caster_ref);
+        self.level.move_to(caster_ref, location);
+        self.journal.record(Effect::Moved(index, from, location));
+        self.events.push(GameEvent::Moved(self.level.monsters[index].mtype, from, location));
+    }
+
+    fn alter_terrain(&mut self, caster_ref: &CasterRef, location: &AbsoluteLocation, alteration: Alteration) -> Option<String> {
+        let kind = self.level.tile(location).kind;
+        let (altered, cost) = match kind.altered(alteration) {
+            Some(altered) => altered,
+            None => return Some(format!("You can't {} the {}", alteration.verb(), kind.name())),
+        };
+        if alteration.needs_empty_tile() && (self.level.is_monster(location) || self.level.items.iter().any(|(loc, _)| loc == location)) {
+            return Some("Something is in the way".to_string())
+        }
+        if self.spend(caster_ref, cost) {
+            self.set_tile(location, Tile::new(altered));
+            None
+        } else {
+            Some(format!("Not enough energy to {} the {}", alteration.verb(), kind.name()))
         }
     }
 
+    // Changes to the terrain are redrawn by the frontends
+    fn set_tile(&mut self, location: &AbsoluteLocation, tile: Tile) {
+        let before = self.level.set_tile(location, tile);
+        self.journal.record(Effect::Terrain(*location, before));
+        self.events.push(GameEvent::TerrainChanged(*location));
+    }
+
+    // Hazards get whoever ends their turn on them, and hidden traps give themselves away
+    fn suffer_hazard(&mut self, index: usize, from: AbsoluteLocation) {
+        let location = match self.level.monsters.get(index) {
+            Some(monster) => monster.location(),
+            None => return,
+        };
+        let hazard = match self.level.tile(&location).hazard(location != from) {
+            Some(hazard) => hazard,
+            None => return,
+        };
+        if let TileKind::Trap(kind, true) = self.level.tile(&location).kind {
+            self.set_tile(&location, Tile::new(TileKind::Trap(kind, false)));
+        }
+        let caster_ref = if index == 0 { CasterRef::Player } else { CasterRef::Monster(index) };
+        let mtype = self.level.monsters[index].mtype;
+        self.events.push(GameEvent::HazardHit(mtype, location, self.level.tile(&location).kind.name().to_string()));
+        match hazard {
+            Hazard::Damage(damage) => self.damage_at(&caster_ref, &location, damage),
+            Hazard::Drain(energy, turns) => {
+                let before = self.level.get_energy(&caster_ref);
+                let drained = energy.min(before);
+                self.level.set_energy(&caster_ref, before - drained);
+                self.journal.record(Effect::Spent(caster_ref, drained, before));
+                self.level.monsters[index].afflict(Condition::Drained, turns);
+            },
+        }
+    }
+
+    // Picking up, dropping and using items can change anything about the monster and the floor
+    fn item_action<F: FnOnce(&mut Level) -> Result<String, String>>(&mut self, caster_ref: &CasterRef, action: F) -> Result<String, String> {
+        let index = self.level.monster_index(caster_ref);
+        let monster = self.level.monsters[index].clone();
+        let floor = self.level.items.clone();
+        let result = action(&mut self.level);
+        if let Ok(description) = result.as_ref() {
+            self.journal.record(Effect::Items(index, description.clone(), monster, floor));
+        }
+        result
+    }
+
     fn clear(&mut self) {
         for i in 0..26 {
             self.registers[i] = 0;
@@ -394,3 +1047,200 @@ impl SpellEngine {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use crate::events::GameEvent;
+    use crate::level::Level;
+    use crate::monster::MonsterType;
+    use crate::tile::TileKind;
+    use crate::item::Item;
+    use crate::journal::RollbackPolicy;
+    use super::{Spellbook, SpellEngine, CasterRef};
+
+    const SPELLS: &str = "
+        :step: store #right a; move a; return;
+        :hit: store #right a; move_cursor a; store 5 b; damage b; return;
+        :twice: store #right a; move a; move a; return;
+    ";
+
+    fn engine(map: &str) -> SpellEngine {
+        SpellEngine::new(Level::parse(&format!("map\n{}\n", map)).unwrap())
+    }
+
+    fn turn(engine: &mut SpellEngine, spell: &str) -> Vec<GameEvent> {
+        engine.do_turn(spell, &Spellbook::compile(SPELLS).unwrap(), &Spellbook::monster_spellbook())
+    }
+
+    #[test]
+    fn labels_can_only_be_defined_once() {
+        let err = Spellbook::compile(":step: return; :step: return;").err().unwrap();
+        assert_eq!((err.offset, err.message.as_str()), (Some(22), "label step is defined twice"));
+        assert!(Spellbook::compile(":step: return; step: return;").is_err());
+    }
+
+    #[test]
+    fn moving_says_who_moved() {
+        let mut engine = engine("@..");
+        assert_eq!(turn(&mut engine, "step"), vec!(
+            GameEvent::EnergySpent(CasterRef::Player, 10),
+            GameEvent::Moved(MonsterType::Player, (0, 0), (1, 0)),
+        ));
+        assert_eq!(engine.turn, 1);
+    }
+
+    #[test]
+    fn moving_into_a_closed_door_opens_it() {
+        let mut engine = engine("@+.");
+        assert_eq!(turn(&mut engine, "step"), vec!(
+            GameEvent::EnergySpent(CasterRef::Player, 2),
+            GameEvent::TerrainChanged((1, 0)),
+        ));
+        assert_eq!(engine.level.location(&CasterRef::Player), (0, 0));
+        assert_eq!(engine.level.tile(&(1, 0)).kind, TileKind::Door(true));
+    }
+
+    #[test]
+    fn attacking_says_who_was_hurt() {
+        let mut engine = engine("@a.");
+        let events = turn(&mut engine, "hit");
+        assert_eq!(events[..4], [
+            GameEvent::EnergySpent(CasterRef::Player, 5),
+            GameEvent::EnergySpent(CasterRef::Player, 5),
+            GameEvent::Attacked((0, 0), (1, 0)),
+            GameEvent::Damaged(MonsterType::Ant, (1, 0), 5),
+        ]);
+        assert_eq!(engine.level.monsters[1].stats.current_hp, 5);
+    }
+
+    #[test]
+    fn killing_says_who_died_and_reveals_their_lore() {
+        let mut engine = engine("@a.");
+        engine.level.monsters[1].stats.current_hp = 3;
+        let events = turn(&mut engine, "hit");
+        assert_eq!(events[3..], [
+            GameEvent::Damaged(MonsterType::Ant, (1, 0), 5),
+            GameEvent::Died(MonsterType::Ant, (1, 0)),
+            GameEvent::SpellRevealed("nova".to_string(), "defeated ant".to_string()),
+        ]);
+        assert_eq!(engine.level.monsters.len(), 1);
+    }
+
+    // Ants that attack whatever is to their left
+    const BITING: &str = ":left: store #left d; move_cursor d; store 5 a; damage a; return; :wait: return;";
+
+    #[test]
+    fn monsters_killed_later_in_the_list_dont_give_extra_turns() {
+        let mut engine = SpellEngine::new(Level::parse("monster = ant 1 0\nmap\n@.a\n").unwrap());
+        engine.level.monsters[2].stats.current_hp = 3;
+        let events = engine.do_turn("wait", &Spellbook::compile(BITING).unwrap(), &Spellbook::compile(BITING).unwrap());
+        assert_eq!(events, vec!(
+            GameEvent::EnergySpent(CasterRef::Monster(1), 5),
+            GameEvent::EnergySpent(CasterRef::Monster(1), 5),
+            GameEvent::Attacked((2, 0), (1, 0)),
+            GameEvent::Damaged(MonsterType::Ant, (1, 0), 5),
+            GameEvent::Died(MonsterType::Ant, (1, 0)),
+        ));
+    }
+
+    #[test]
+    fn monsters_moved_down_the_list_still_suffer_hazards() {
+        let mut engine = SpellEngine::new(Level::parse("monster = ant 3 0\nmap\n@.a}\n").unwrap());
+        engine.level.monsters[1].stats.current_hp = 3;
+        let events = engine.do_turn("wait", &Spellbook::compile(BITING).unwrap(), &Spellbook::compile(BITING).unwrap());
+        assert!(events.contains(&GameEvent::Died(MonsterType::Ant, (2, 0))));
+        assert!(events.contains(&GameEvent::HazardHit(MonsterType::Ant, (3, 0), "lava".to_string())));
+    }
+
+    #[test]
+    fn the_game_ends_when_the_player_dies() {
+        // Both ants bite, but the first one kills the player
+        let spellbook = Spellbook::compile(&format!("{} :right: store #right d; move_cursor d; store 5 a; damage a; return;", BITING)).unwrap();
+        let mut engine = engine("a@a");
+        engine.level.monsters[0].stats.current_hp = 4;
+        assert_eq!(engine.do_turn("wait", &spellbook, &spellbook), vec!(
+            GameEvent::EnergySpent(CasterRef::Monster(1), 5),
+            GameEvent::EnergySpent(CasterRef::Monster(1), 5),
+            GameEvent::Attacked((0, 0), (1, 0)),
+            GameEvent::Damaged(MonsterType::Player, (1, 0), 5),
+            GameEvent::Died(MonsterType::Player, (1, 0)),
+        ));
+        assert_eq!(engine.level.monsters.len(), 3);
+        assert!(engine.level.monsters[0].is_dead());
+        assert_eq!(engine.do_turn("wait", &spellbook, &spellbook), vec!(
+            GameEvent::SpellFailed(CasterRef::Player, "wait".to_string(), "You are dead".to_string()),
+        ));
+        assert_eq!(engine.turn, 1);
+    }
+
+    #[test]
+    fn blasts_dont_go_through_walls() {
+        let spellbook = Spellbook::compile(":blast: store 1 a; store 2 r; damage_radius a r; return;").unwrap();
+        let mut engine = engine("@|a\n...");
+        let events = engine.do_turn("blast", &spellbook, &Spellbook::monster_spellbook());
+        assert_eq!(events[0], GameEvent::EnergySpent(CasterRef::Player, 4));
+        assert_eq!(engine.level.monsters[1].stats.current_hp, 10);
+    }
+
+    #[test]
+    fn arithmetic_errors_fail_the_spell() {
+        let spellbook = Spellbook::compile("
+            :divide: store #right a; move a; store 0 b; div a b c; return;
+            :overflow: store 2147483647 a; store 1 b; add a b c; return;
+        ").unwrap();
+        let mut engine = engine("@..");
+        assert_eq!(engine.do_turn("divide", &spellbook, &Spellbook::monster_spellbook()), vec!(
+            GameEvent::SpellFailed(CasterRef::Player, "divide".to_string(), "Division by zero, so the spell unravels".to_string()),
+        ));
+        assert_eq!(engine.level.location(&CasterRef::Player), (0, 0));
+        assert_eq!(engine.do_turn("overflow", &spellbook, &Spellbook::monster_spellbook()), vec!(
+            GameEvent::SpellFailed(CasterRef::Player, "overflow".to_string(), "Arithmetic overflow, so the spell unravels".to_string()),
+        ));
+    }
+
+    #[test]
+    fn failed_spells_are_rolled_back() {
+        let mut engine = engine("@..");
+        assert_eq!(turn(&mut engine, "twice"), vec!(
+            GameEvent::SpellFailed(CasterRef::Player, "twice".to_string(), "Not enough energy to move, so the spell unravels".to_string()),
+        ));
+        assert_eq!(engine.level.location(&CasterRef::Player), (0, 0));
+        assert_eq!(engine.level.get_energy(&CasterRef::Player), 10);
+        assert_eq!(engine.turn, 0);
+    }
+
+    const WAVING: &str = ":wave: store #right a; move a; store 0 b; use_item b; return;";
+
+    fn holding(map: &str, mut item: Item) -> SpellEngine {
+        let mut engine = engine(map);
+        item.spell.as_mut().unwrap().caster.energy = 0;
+        engine.level.monsters[0].inventory.push(item);
+        engine
+    }
+
+    #[test]
+    fn spells_are_undone_with_the_items_they_use() {
+        let mut engine = holding("@..a", Item::wand_of_fire());
+        let events = engine.do_turn("wave", &Spellbook::compile(WAVING).unwrap(), &Spellbook::monster_spellbook());
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            GameEvent::SpellFailed(CasterRef::Item(0, 0), _, _) => (),
+            event => panic!("expected the wand to fail, got {:?}", event),
+        }
+        assert_eq!(engine.level.location(&CasterRef::Player), (0, 0));
+        assert_eq!(engine.level.get_energy(&CasterRef::Player), 10);
+        assert_eq!(engine.level.location(&CasterRef::Monster(1)), (3, 0));
+        assert_eq!(engine.turn, 0);
+    }
+
+    #[test]
+    fn failed_items_still_take_the_turn_when_effects_are_kept() {
+        let mut engine = holding("@..a", Item::scroll_of_lightning());
+        engine.rollback = RollbackPolicy::KeepEffects;
+        engine.do_turn("wave", &Spellbook::compile(WAVING).unwrap(), &Spellbook::monster_spellbook());
+        assert_eq!(engine.level.location(&CasterRef::Player), (1, 0));
+        assert_eq!(engine.turn, 1);
+        // Only scrolls that work are used up
+        assert_eq!(engine.level.monsters[0].inventory.len(), 1);
+    }
+}
@@ -0,0 +1,340 @@
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+
+use libc;
+use quicksilver::input::Key;
+
+use crate::game::Game;
+use crate::level::Level;
+use crate::events::GameEvent;
+use crate::keymap::{Keymap, KeyChord, key_from_name, letter_index};
+use crate::messages::MessageCategory;
+use crate::monster::Faction;
+use crate::tile::TileKind;
+use crate::utils::AbsoluteLocation;
+
+const KEYMAP_FILE: &'static str = "static/keymap.txt";
+pub const LEVEL_FILE: &'static str = "static/level.txt";
+const HUD_MESSAGES: usize = 5;
+const HELP: &'static str = "F1 help  F3 inventory  F4 spellbook  F7 undo policy  ctrl+c quit";
+
+// Characters typed with shift on a US keyboard, and the key they're on
+const SHIFTED: &[(char, &str)] = &[
+    (')', "0"), ('!', "1"), ('@', "2"), ('#', "3"), ('$', "4"), ('%', "5"), ('^', "6"), ('&', "7"),
+    ('*', "8"), ('(', "9"), ('>', "period"), ('<', "comma"), ('?', "slash"), (':', "semicolon"),
+    ('"', "apostrophe"), ('_', "minus"), ('+', "equals"), ('{', "lbracket"), ('}', "rbracket"),
+    ('|', "backslash"), ('~', "grave"),
+];
+const UNSHIFTED: &[(char, &str)] = &[
+    ('.', "period"), (',', "comma"), ('/', "slash"), (';', "semicolon"), ('\'', "apostrophe"),
+    ('-', "minus"), ('=', "equals"), ('[', "lbracket"), (']', "rbracket"), ('\\', "backslash"),
+    ('`', "grave"), (' ', "space"),
+];
+
+#[derive(Copy, Clone, PartialEq)]
+enum Screen {
+    Map,
+    Help,
+    Inventory,
+    Spellbook,
+}
+
+// Plays the game in the terminal with the same keymap as the graphical frontend, for playing
+// over SSH or without a GPU. Only works on unix terminals.
+pub fn run(level_path: &str) -> Result<(), String> {
+    let source = fs::read_to_string(level_path).map_err(|err| format!("{}: {}", level_path, err))?;
+    let level = Level::parse(&source).map_err(|err| format!("{}: {}", level_path, err))?;
+    let mut game = Game::new(level);
+    let source = fs::read_to_string(KEYMAP_FILE).map_err(|err| format!("{}: {}", KEYMAP_FILE, err))?;
+    let keymap = Keymap::parse(&source).map_err(|err| format!("{}: {}", KEYMAP_FILE, err))?;
+    let terminal = RawTerminal::enter()?;
+    let mut screen = Screen::Map;
+    let mut blast = Vec::new();
+    loop {
+        draw(&game, &keymap, screen, &blast).map_err(|err| err.to_string())?;
+        for chord in terminal.read_keys()? {
+            if chord.ctrl && (chord.key == Key::C || chord.key == Key::D) {
+                return Ok(())
+            }
+            screen = match (screen, chord.key) {
+                (Screen::Help, Key::F1) | (Screen::Inventory, Key::F3) | (Screen::Spellbook, Key::F4) | (_, Key::Escape) => Screen::Map,
+                (_, Key::F1) => Screen::Help,
+                (_, Key::F3) => Screen::Inventory,
+                (_, Key::F4) => Screen::Spellbook,
+                (_, Key::F7) => {
+                    game.cycle_rollback();
+                    screen
+                },
+                (Screen::Inventory, key) => {
+                    if let Some(slot) = letter_index(key) {
+                        let spell = if chord.shift { "drop" } else { "use_item" };
+                        blast = run_turns(&mut game, &[spell.to_string()], &[slot as i32]);
+                    }
+                    screen
+                },
+                (Screen::Spellbook, key) => {
+                    if let Some(index) = letter_index(key) {
+                        if chord.shift {
+                            game.forget(index);
+                        } else {
+                            game.toggle_inscribed(index);
+                        }
+                    }
+                    screen
+                },
+                (screen, _) => {
+                    if let Some(spells) = keymap.get(&chord) {
+                        blast = run_turns(&mut game, spells, &[]);
+                    }
+                    screen
+                },
+            };
+        }
+    }
+}
+
+// A failed spell drops the rest of the binding, like in the graphical frontend.
+// Returns the tiles hit by area spells on the last turn.
+fn run_turns(game: &mut Game, spells: &[String], args: &[i32]) -> Vec<AbsoluteLocation> {
+    let mut blast = Vec::new();
+    for spell in spells {
+        blast.clear();
+        let (events, succeeded) = game.run_turn(spell, args);
+        for event in events {
+            if let GameEvent::Blast(area) = event {
+                blast.extend(area);
+            }
+        }
+        if !succeeded {
+            break;
+        }
+    }
+    blast
+}
+
+fn draw(game: &Game, keymap: &Keymap, screen: Screen, blast: &[AbsoluteLocation]) -> io::Result<()> {
+    let level = &game.spell_engine.level;
+    let player = &level.monsters[0];
+    let mut lines = match screen {
+        Screen::Map => map_lines(game, blast),
+        Screen::Help => {
+            let describe_spell = |spell: &str| match game.player_spellbook.cost(spell) {
+                Some(cost) => format!("{} ({})", spell, cost),
+                None => spell.to_string(),
+            };
+            let mut lines = vec!(bold("Keys (F1 or escape to close)"));
+            lines.extend(keymap.describe(&describe_spell));
+            lines
+        },
+        Screen::Inventory => {
+            let mut lines = vec!(bold("Inventory (letter to use, shift+letter to drop, F3 to close)"));
+            if player.inventory.is_empty() {
+                lines.push("You are not carrying anything".to_string());
+            }
+            for (i, item) in player.inventory.iter().take(26).enumerate() {
+                let equipped = if item.equipped { " (in use)" } else { "" };
+                lines.push(format!("{} - {} {}{}", (b'a' + i as u8) as char, item.symbol(), item.describe(), equipped));
+            }
+            lines
+        },
+        Screen::Spellbook => {
+            let mut lines = vec!(bold("Spellbook (letter to inscribe/erase, shift+letter to forget, F4 to close)"));
+            for (i, spell) in game.spell_memory.spells().iter().take(26).enumerate() {
+                let status = if spell.inscribed { "inscribed" } else { "not inscribed" };
+                let cost = game.player_spellbook.cost(&spell.label).map(|cost| format!(", costs {}", cost)).unwrap_or_default();
+                lines.push(format!("{} - {} ({}, from {}{})", (b'a' + i as u8) as char, spell.label, status, spell.source, cost));
+            }
+            lines
+        },
+    };
+    lines.push(String::new());
+    let effects: Vec<String> = player.effects.iter().map(|effect| format!("  {} ({})", effect.condition.name(), effect.turns_left)).collect();
+    lines.push(format!("HP {}/{}  EN {}/{}  Depth {}  Turn {}{}", player.stats.current_hp, player.stats.max_hp,
+        player.caster.energy, player.caster.max_energy(), level.depth, game.spell_engine.turn, effects.concat()));
+    for message in game.log.recent(HUD_MESSAGES) {
+        lines.push(colored(&message.text, category_color(message.category)));
+    }
+    lines.push(HELP.to_string());
+
+    // Raw mode doesn't turn newlines into carriage returns
+    let mut out = io::stdout();
+    write!(out, "\x1b[H\x1b[2J{}", lines.join("\r\n"))?;
+    out.flush()
+}
+
+// Colors are ANSI SGR codes
+fn map_lines(game: &Game, blast: &[AbsoluteLocation]) -> Vec<String> {
+    let level = &game.spell_engine.level;
+    (0..level.terrain[0].len()).map(|row| (0..level.terrain.len()).map(|col| {
+        let (glyph, color) = if let Some(monster) = level.monsters.iter().find(|monster| monster.location() == (col, row)) {
+            (monster.mtype.symbol(), if monster.faction() == Faction::Player { "1;37" } else { "1;31" })
+        } else if let Some((_, item)) = level.items.iter().find(|(location, _)| *location == (col, row)) {
+            (item.symbol(), "33")
+        } else {
+            let tile = level.terrain[col][row];
+            (tile.symbol(), match tile.kind {
+                TileKind::Door(_) => "33",
+                TileKind::Water => "34",
+                TileKind::Lava => "31",
+                TileKind::Trap(_, false) => "35",
+                _ => "37",
+            })
+        };
+        let background = if blast.contains(&(col, row)) { ";41" } else { "" };
+        format!("\x1b[{}{}m{}\x1b[0m", color, background, glyph)
+    }).collect::<String>()).collect()
+}
+
+fn colored(text: &str, color: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", color, text)
+}
+
+fn bold(text: &str) -> String {
+    colored(text, "1;33")
+}
+
+fn category_color(category: MessageCategory) -> &'static str {
+    match category {
+        MessageCategory::Combat => "33",
+        MessageCategory::Item => "32",
+        MessageCategory::SpellError => "31",
+        MessageCategory::System => "37",
+    }
+}
+
+// Puts the terminal in raw mode on the alternate screen, and puts it back when dropped
+struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    fn enter() -> Result<RawTerminal, String> {
+        let original = unsafe {
+            let mut original: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err("standard input is not a terminal".to_string())
+            }
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(format!("couldn't set up the terminal: {}", io::Error::last_os_error()))
+            }
+            original
+        };
+        print!("\x1b[?1049h\x1b[?25l");
+        Ok(RawTerminal {
+            original: original,
+        })
+    }
+
+    // Waits for at least one key press
+    fn read_keys(&self) -> Result<Vec<KeyChord>, String> {
+        let mut buffer = [0u8; 64];
+        let read = unsafe { libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+        if read <= 0 {
+            return Err("the terminal closed".to_string())
+        }
+        Ok(parse_keys(&buffer[..read as usize]))
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+    }
+}
+
+// Decodes what the terminal sends for key presses, skipping anything the keymap has no key for
+fn parse_keys(bytes: &[u8]) -> Vec<KeyChord> {
+    let mut chords = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let (chord, length) = parse_key(&bytes[start..]);
+        chords.extend(chord);
+        start += length;
+    }
+    chords
+}
+
+// The first key in `bytes` and how many bytes it took
+fn parse_key(bytes: &[u8]) -> (Option<KeyChord>, usize) {
+    let chord = |key, shift, ctrl, alt| Some(KeyChord { key: key, shift: shift, ctrl: ctrl, alt: alt });
+    match bytes[0] {
+        0x1b => match bytes.get(1) {
+            None => (chord(Key::Escape, false, false, false), 1),
+            Some(b'[') | Some(b'O') => parse_escape_sequence(bytes),
+            Some(_) => {
+                let (key, length) = parse_key(&bytes[1..]);
+                (key.map(|key| KeyChord { alt: true, ..key }), length + 1)
+            },
+        },
+        b'\r' | b'\n' => (chord(Key::Return, false, false, false), 1),
+        b'\t' => (chord(Key::Tab, false, false, false), 1),
+        0x7f | 0x08 => (chord(Key::Back, false, false, false), 1),
+        byte @ 1..=26 => (key_from_name(&((b'a' + byte - 1) as char).to_string()).and_then(|key| chord(key, false, true, false)), 1),
+        byte if byte < 0x80 => {
+            let c = byte as char;
+            let key = if c.is_ascii_lowercase() || c.is_ascii_digit() {
+                key_from_name(&c.to_string()).and_then(|key| chord(key, false, false, false))
+            } else if c.is_ascii_uppercase() {
+                key_from_name(&c.to_ascii_lowercase().to_string()).and_then(|key| chord(key, true, false, false))
+            } else if let Some((_, name)) = SHIFTED.iter().find(|(shifted, _)| *shifted == c) {
+                key_from_name(name).and_then(|key| chord(key, true, false, false))
+            } else {
+                UNSHIFTED.iter().find(|(unshifted, _)| *unshifted == c)
+                    .and_then(|(_, name)| key_from_name(name)).and_then(|key| chord(key, false, false, false))
+            };
+            (key, 1)
+        },
+        // Anything else is the start of a character outside ASCII
+        _ => (None, 1 + bytes[1..].iter().take_while(|byte| **byte & 0xc0 == 0x80).count()),
+    }
+}
+
+// `ESC [ params final` or `ESC O final`, where the last parameter can hold modifiers
+fn parse_escape_sequence(bytes: &[u8]) -> (Option<KeyChord>, usize) {
+    let end = match bytes.iter().skip(2).position(|byte| (0x40..=0x7e).contains(byte)) {
+        Some(end) => end + 2,
+        None => return (None, bytes.len()),
+    };
+    let params: Vec<u32> = String::from_utf8_lossy(&bytes[2..end]).split(';').map(|param| param.parse().unwrap_or(1)).collect();
+    let key = match (bytes[end], params[0]) {
+        (b'A', _) => Some(Key::Up),
+        (b'B', _) => Some(Key::Down),
+        (b'C', _) => Some(Key::Right),
+        (b'D', _) => Some(Key::Left),
+        (b'H', _) | (b'~', 1) | (b'~', 7) => Some(Key::Home),
+        (b'F', _) | (b'~', 4) | (b'~', 8) => Some(Key::End),
+        (b'P', _) | (b'~', 11) => Some(Key::F1),
+        (b'Q', _) | (b'~', 12) => Some(Key::F2),
+        (b'R', _) | (b'~', 13) => Some(Key::F3),
+        (b'S', _) | (b'~', 14) => Some(Key::F4),
+        (b'~', 2) => Some(Key::Insert),
+        (b'~', 3) => Some(Key::Delete),
+        (b'~', 5) => Some(Key::PageUp),
+        (b'~', 6) => Some(Key::PageDown),
+        (b'~', 15) => Some(Key::F5),
+        (b'~', 17) => Some(Key::F6),
+        (b'~', 18) => Some(Key::F7),
+        (b'~', 19) => Some(Key::F8),
+        (b'~', 20) => Some(Key::F9),
+        (b'~', 21) => Some(Key::F10),
+        (b'~', 23) => Some(Key::F11),
+        (b'~', 24) => Some(Key::F12),
+        _ => None,
+    };
+    // The modifier parameter is one more than a bit set of shift, alt and ctrl
+    let modifiers = if params.len() > 1 { params[params.len() - 1].saturating_sub(1) } else { 0 };
+    let chord = key.map(|key| KeyChord {
+        key: key,
+        shift: modifiers & 1 != 0,
+        alt: modifiers & 2 != 0,
+        ctrl: modifiers & 4 != 0,
+    });
+    (chord, end + 1)
+}
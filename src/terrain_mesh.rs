@@ -0,0 +1,62 @@
+use quicksilver::{
+    geom::{Rectangle, Vector, Transform},
+    graphics::{Image, Mesh, Drawable, Vertex, GpuTriangle, Background::Img},
+};
+
+use crate::level::Level;
+
+// The level's terrain tessellated once, with a small mesh per tile so only the tiles on screen
+// are copied into each frame. Bake a new one whenever the terrain or tileset changes.
+pub struct TerrainMesh {
+    tile_size: Vector,
+    tiles: Vec<Vec<Mesh>>, // indexed [col][row] like the terrain, empty where there is nothing to draw
+}
+
+impl TerrainMesh {
+    pub fn bake(level: &Level, tile_size: Vector, sprite: &dyn Fn(&str) -> Image) -> TerrainMesh {
+        let tiles = level.terrain.iter().enumerate().map(|(col, column)| {
+            column.iter().enumerate().map(|(row, tile)| {
+                let mut mesh = Mesh::new();
+                if let Some(name) = tile.sprite {
+                    let area = Rectangle::new(Vector::new(col as f32, row as f32).times(tile_size), tile_size);
+                    area.draw(&mut mesh, Img(&sprite(name)), Transform::IDENTITY, 0);
+                }
+                mesh
+            }).collect()
+        }).collect();
+        TerrainMesh {
+            tile_size: tile_size,
+            tiles: tiles,
+        }
+    }
+
+    // `view` is the part of the level on screen, in pixels before the camera transform
+    pub fn draw(&self, mesh: &mut Mesh, camera: Transform, view: &Rectangle) {
+        let cols = tile_range(view.pos.x, view.size.x, self.tile_size.x, self.tiles.len());
+        let rows = tile_range(view.pos.y, view.size.y, self.tile_size.y, self.tiles.get(0).map(|column| column.len()).unwrap_or(0));
+        for col in cols.0..cols.1 {
+            for row in rows.0..rows.1 {
+                let tile = &self.tiles[col][row];
+                let offset = mesh.vertices.len() as u32;
+                mesh.vertices.extend(tile.vertices.iter().map(|vertex| Vertex {
+                    pos: camera * vertex.pos,
+                    ..*vertex
+                }));
+                mesh.triangles.extend(tile.triangles.iter().map(|triangle| {
+                    let mut triangle: GpuTriangle = triangle.clone();
+                    for index in triangle.indices.iter_mut() {
+                        *index += offset;
+                    }
+                    triangle
+                }));
+            }
+        }
+    }
+}
+
+// The tiles from `start` to `start + length` pixels along one axis, clamped to the level
+pub fn tile_range(start: f32, length: f32, tile_size: f32, tiles: usize) -> (usize, usize) {
+    let first = (start / tile_size).floor().max(0.0) as usize;
+    let last = ((start + length) / tile_size).ceil().max(0.0) as usize;
+    (first.min(tiles), last.min(tiles))
+}
@@ -1,68 +1,302 @@
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TrapKind {
+    Spikes,
+    Drain,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TileKind {
+    Nothing, // solid rock
+    Floor,
+    Wall,
+    Door(bool), // open
+    Water, // too deep to wade through, but spells fly over it
+    Lava,
+    Trap(TrapKind, bool), // hidden
+    StairsUp,
+    StairsDown,
+}
+
+// What a tile does to whoever ends their turn on it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Hazard {
+    Damage(u32),
+    Drain(u32, u32), // energy, turns it stops coming back
+}
+
+// Ways spells can change the terrain at the cursor
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Alteration {
+    Dig,
+    BuildWall,
+    Open,
+    Close,
+    Ignite,
+}
+
+// Every kind of tile, for working out what alterations can cost
+const KINDS: &[TileKind] = &[
+    TileKind::Nothing, TileKind::Floor, TileKind::Wall, TileKind::Door(false), TileKind::Door(true), TileKind::Water,
+    TileKind::Lava, TileKind::Trap(TrapKind::Spikes, false), TileKind::Trap(TrapKind::Spikes, true),
+    TileKind::Trap(TrapKind::Drain, false), TileKind::Trap(TrapKind::Drain, true), TileKind::StairsUp, TileKind::StairsDown,
+];
+
+impl Alteration {
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Alteration::Dig => "dig through",
+            Alteration::BuildWall => "build a wall on",
+            Alteration::Open => "open",
+            Alteration::Close => "close",
+            Alteration::Ignite => "set fire to",
+        }
+    }
+
+    // Nothing may be standing or lying where a door closes or a wall goes up
+    pub fn needs_empty_tile(&self) -> bool {
+        match self {
+            Alteration::BuildWall | Alteration::Close => true,
+            _ => false,
+        }
+    }
+
+    // The cheapest and dearest tiles to alter this way
+    pub fn cost_range(&self) -> (u32, u32) {
+        let costs: Vec<u32> = KINDS.iter().filter_map(|kind| kind.altered(*self)).map(|(_, cost)| cost).collect();
+        (costs.iter().cloned().min().unwrap_or(0), costs.iter().cloned().max().unwrap_or(0))
+    }
+}
+
+// Codes reported by the `tile_kind` syscall, also the `#name` constants spells compare them with
+pub const KIND_NAMES: &[&str] = &[
+    "nothing", "floor", "wall", "closed_door", "open_door", "water", "lava", "trap", "stairs_up", "stairs_down",
+];
+
+impl TileKind {
+    // Traps nobody has found yet pass for floor
+    pub fn code(&self) -> i32 {
+        let name = match self {
+            TileKind::Nothing => "nothing",
+            TileKind::Floor | TileKind::Trap(_, true) => "floor",
+            TileKind::Wall => "wall",
+            TileKind::Door(false) => "closed_door",
+            TileKind::Door(true) => "open_door",
+            TileKind::Water => "water",
+            TileKind::Lava => "lava",
+            TileKind::Trap(_, false) => "trap",
+            TileKind::StairsUp => "stairs_up",
+            TileKind::StairsDown => "stairs_down",
+        };
+        TileKind::code_from_name(name).unwrap()
+    }
+
+    // The names used by level files, where traps start out hidden
+    pub fn from_name(name: &str) -> Option<TileKind> {
+        match name {
+            "nothing" => Some(TileKind::Nothing),
+            "floor" => Some(TileKind::Floor),
+            "wall" => Some(TileKind::Wall),
+            "closed_door" => Some(TileKind::Door(false)),
+            "open_door" => Some(TileKind::Door(true)),
+            "water" => Some(TileKind::Water),
+            "lava" => Some(TileKind::Lava),
+            "spike_trap" => Some(TileKind::Trap(TrapKind::Spikes, true)),
+            "drain_trap" => Some(TileKind::Trap(TrapKind::Drain, true)),
+            "stairs_up" => Some(TileKind::StairsUp),
+            "stairs_down" => Some(TileKind::StairsDown),
+            _ => None,
+        }
+    }
+
+    pub fn code_from_name(name: &str) -> Option<i32> {
+        KIND_NAMES.iter().position(|kind| *kind == name).map(|code| code as i32)
+    }
+
+    pub fn is_passable(&self) -> bool {
+        match self {
+            TileKind::Nothing | TileKind::Wall | TileKind::Door(false) | TileKind::Water => false,
+            _ => true,
+        }
+    }
+
+    // Area spells reach every transparent tile
+    pub fn is_transparent(&self) -> bool {
+        match self {
+            TileKind::Nothing | TileKind::Wall | TileKind::Door(false) => false,
+            _ => true,
+        }
+    }
+
+    pub fn is_flammable(&self) -> bool {
+        match self {
+            TileKind::Door(_) => true,
+            _ => false,
+        }
+    }
+
+    // What the tile becomes and the energy that takes, None if it can't be done. Hidden traps
+    // are altered like the floor they pass for.
+    pub fn altered(&self, alteration: Alteration) -> Option<(TileKind, u32)> {
+        match (alteration, *self) {
+            (Alteration::Dig, TileKind::Nothing) => Some((TileKind::Floor, 5)),
+            (Alteration::Dig, TileKind::Wall) => Some((TileKind::Floor, 4)),
+            (Alteration::Dig, TileKind::Door(_)) => Some((TileKind::Floor, 3)),
+            (Alteration::Dig, TileKind::Trap(_, false)) => Some((TileKind::Floor, 2)), // digs it out
+            (Alteration::BuildWall, TileKind::Floor) | (Alteration::BuildWall, TileKind::Trap(_, true)) => Some((TileKind::Wall, 4)),
+            (Alteration::BuildWall, TileKind::Water) => Some((TileKind::Wall, 5)),
+            (Alteration::Open, TileKind::Door(false)) => Some((TileKind::Door(true), 2)),
+            (Alteration::Close, TileKind::Door(true)) => Some((TileKind::Door(false), 2)),
+            (Alteration::Ignite, kind) if kind.is_flammable() => Some((TileKind::Floor, 3)), // burns away
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TileKind::Nothing => "solid rock",
+            TileKind::Floor | TileKind::Trap(_, true) => "floor",
+            TileKind::Wall => "wall",
+            TileKind::Door(false) => "closed door",
+            TileKind::Door(true) => "open door",
+            TileKind::Water => "water",
+            TileKind::Lava => "lava",
+            TileKind::Trap(TrapKind::Spikes, false) => "spike trap",
+            TileKind::Trap(TrapKind::Drain, false) => "drain trap",
+            TileKind::StairsUp => "staircase up",
+            TileKind::StairsDown => "staircase down",
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Tile {
-    pub sprite_index: Option<usize>,
-    pub is_wall: bool,
-    // TODO add more stuff like passable, etc.
+    pub kind: TileKind,
+    pub sprite: Option<&'static str>, // name in the tileset
 }
 
 impl Tile {
-    pub fn nothing() -> Tile {
+    // Walls and doors get a placeholder sprite until `Level::autotile_walls` picks the one that fits
+    pub fn new(kind: TileKind) -> Tile {
+        let sprite = match kind {
+            TileKind::Nothing => None,
+            TileKind::Floor | TileKind::Trap(_, true) => Some("floor"),
+            TileKind::Wall => Some("wall_vert"),
+            TileKind::Door(open) => Some(Tile::door_sprite(open, false)),
+            TileKind::Water => Some("water"),
+            TileKind::Lava => Some("lava"),
+            TileKind::Trap(TrapKind::Spikes, false) => Some("spike_trap"),
+            TileKind::Trap(TrapKind::Drain, false) => Some("drain_trap"),
+            TileKind::StairsUp => Some("stairs_up"),
+            TileKind::StairsDown => Some("stairs_down"),
+        };
         Tile {
-            sprite_index: None,
-            is_wall: true,
+            kind: kind,
+            sprite: sprite,
         }
     }
 
+    pub fn nothing() -> Tile {
+        Tile::new(TileKind::Nothing)
+    }
+
     pub fn floor() -> Tile {
-        Tile {
-            sprite_index: Some(870),
-            is_wall: false,
-        }
+        Tile::new(TileKind::Floor)
     }
 
-    pub fn vert_wall() -> Tile {
-        Tile {
-            sprite_index: Some(851),
-            is_wall: true,
-        }
+    pub fn wall() -> Tile {
+        Tile::new(TileKind::Wall)
     }
 
-    pub fn horiz_wall() -> Tile {
-        Tile {
-            sprite_index: Some(852),
-            is_wall: true,
+    // Classic roguelike glyph, used by the text level format and the terminal
+    pub fn symbol(&self) -> char {
+        match self.kind {
+            TileKind::Nothing => ' ',
+            TileKind::Floor | TileKind::Trap(_, true) => '.',
+            TileKind::Wall => match self.sprite {
+                Some("wall_vert") => '|',
+                Some("wall_horiz") => '-',
+                _ => '#', // corners and junctions
+            },
+            TileKind::Door(false) => '+',
+            TileKind::Door(true) => '\'',
+            TileKind::Water => '~',
+            TileKind::Lava => '}',
+            TileKind::Trap(TrapKind::Spikes, false) => '^',
+            TileKind::Trap(TrapKind::Drain, false) => '"',
+            TileKind::StairsUp => '<',
+            TileKind::StairsDown => '>',
         }
     }
 
-    pub fn top_left_wall() -> Tile {
-        Tile {
-            sprite_index: Some(853),
-            is_wall: true,
+    // Traps only go off as they are stepped on, lava burns every turn
+    pub fn hazard(&self, arrived: bool) -> Option<Hazard> {
+        match self.kind {
+            TileKind::Lava => Some(Hazard::Damage(8)),
+            TileKind::Trap(TrapKind::Spikes, _) if arrived => Some(Hazard::Damage(4)),
+            TileKind::Trap(TrapKind::Drain, _) if arrived => Some(Hazard::Drain(20, 3)),
+            _ => None,
         }
     }
 
-    pub fn top_right_wall() -> Tile {
-        Tile {
-            sprite_index: Some(854),
-            is_wall: true,
+    // Doors sit in walls, so they join up with them too. Solid rock has nothing to join up with.
+    pub fn joins_walls(&self) -> bool {
+        match self.kind {
+            TileKind::Wall | TileKind::Door(_) => true,
+            _ => false,
         }
     }
 
-    pub fn bot_left_wall() -> Tile {
-        Tile {
-            sprite_index: Some(855),
-            is_wall: true,
+    // The wall sprite that joins up with walls on the given sides
+    pub fn wall_sprite(up: bool, down: bool, left: bool, right: bool) -> &'static str {
+        match (up, down, left, right) {
+            (true, true, true, true) => "wall_cross",
+            (true, true, true, false) => "wall_t_left",
+            (true, true, false, true) => "wall_t_right",
+            (true, false, true, true) => "wall_t_up",
+            (false, true, true, true) => "wall_t_down",
+            (false, true, false, true) => "wall_top_left",
+            (false, true, true, false) => "wall_top_right",
+            (true, false, false, true) => "wall_bottom_left",
+            (true, false, true, false) => "wall_bottom_right",
+            (_, _, false, false) => "wall_vert", // walls on their own too
+            (false, false, _, _) => "wall_horiz",
         }
     }
 
-    pub fn bot_right_wall() -> Tile {
-        Tile {
-            sprite_index: Some(856),
-            is_wall: true,
+    // A door in a vertical wall, with walls above and below it, is drawn differently
+    pub fn door_sprite(open: bool, vertical: bool) -> &'static str {
+        match (open, vertical) {
+            (false, false) => "door_closed_horiz",
+            (false, true) => "door_closed_vert",
+            (true, false) => "door_open_horiz",
+            (true, true) => "door_open_vert",
         }
     }
-    
-    // TODO
 }
-   
 
+#[cfg(test)]
+mod tests {
+    use super::Tile;
+
+    #[test]
+    fn wall_sprites_join_the_walls_beside_them() {
+        assert_eq!(Tile::wall_sprite(true, true, true, true), "wall_cross");
+        assert_eq!(Tile::wall_sprite(true, true, true, false), "wall_t_left");
+        assert_eq!(Tile::wall_sprite(true, true, false, true), "wall_t_right");
+        assert_eq!(Tile::wall_sprite(true, false, true, true), "wall_t_up");
+        assert_eq!(Tile::wall_sprite(false, true, true, true), "wall_t_down");
+        assert_eq!(Tile::wall_sprite(false, true, false, true), "wall_top_left");
+        assert_eq!(Tile::wall_sprite(false, true, true, false), "wall_top_right");
+        assert_eq!(Tile::wall_sprite(true, false, false, true), "wall_bottom_left");
+        assert_eq!(Tile::wall_sprite(true, false, true, false), "wall_bottom_right");
+    }
+
+    #[test]
+    fn straight_and_lone_walls() {
+        assert_eq!(Tile::wall_sprite(true, true, false, false), "wall_vert");
+        assert_eq!(Tile::wall_sprite(true, false, false, false), "wall_vert");
+        assert_eq!(Tile::wall_sprite(false, false, false, false), "wall_vert");
+        assert_eq!(Tile::wall_sprite(false, false, true, true), "wall_horiz");
+        assert_eq!(Tile::wall_sprite(false, false, false, true), "wall_horiz");
+    }
+}
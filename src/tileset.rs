@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+// Drawn in place of any sprite a tileset doesn't name
+const UNKNOWN_SPRITE: &'static str = "unknown";
+
+// Where each named sprite is on a sprite sheet, counting across the rows from the top left
+pub struct Tileset {
+    pub image: String, // file name of the sprite sheet
+    pub sprite_size: u32,
+    sprites: HashMap<String, usize>,
+}
+
+impl Tileset {
+    // Format is one `name = value` per line with `#` comments. `image` and `sprite_size` describe
+    // the sheet, every other name is a sprite.
+    pub fn parse(source: &str) -> Result<Tileset, String> {
+        let mut image = None;
+        let mut sprite_size = None;
+        let mut sprites = HashMap::new();
+        for (i, line) in source.lines().enumerate() {
+            let line_num = i + 1;
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut sides = line.splitn(2, '=').map(|side| side.trim());
+            let name = sides.next().unwrap();
+            let value = match sides.next() {
+                Some(value) if !name.is_empty() && !value.is_empty() => value,
+                _ => return Err(format!("line {}: expected `name = value`", line_num)),
+            };
+            match name {
+                "image" => image = Some(value.to_string()),
+                "sprite_size" => sprite_size = Some(value.parse::<u32>().ok().filter(|size| *size > 0)
+                    .ok_or_else(|| format!("line {}: sprite size '{}' is not a positive number", line_num, value))?),
+                name => {
+                    let index = value.parse::<usize>().map_err(|_| format!("line {}: sprite index '{}' is not a number", line_num, value))?;
+                    if sprites.insert(name.to_string(), index).is_some() {
+                        return Err(format!("line {}: '{}' is already defined", line_num, name))
+                    }
+                },
+            }
+        }
+        if !sprites.contains_key(UNKNOWN_SPRITE) {
+            return Err(format!("no '{}' sprite", UNKNOWN_SPRITE))
+        }
+        Ok(Tileset {
+            image: image.ok_or("no image")?,
+            sprite_size: sprite_size.ok_or("no sprite_size")?,
+            sprites: sprites,
+        })
+    }
+
+    pub fn index(&self, sprite: &str) -> usize {
+        self.sprites.get(sprite).or_else(|| self.sprites.get(UNKNOWN_SPRITE)).cloned().unwrap()
+    }
+}
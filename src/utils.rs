@@ -1,15 +1,17 @@
 pub type AbsoluteLocation = (usize, usize);
 pub type RelativeLocation = (isize, isize);
 
+// The numbering is shared by the spell grammar's direction constants and the VM
+#[derive(Copy, Clone)]
 pub enum Direction {
-    Left,
-    Right,
-    Up,
-    Down,
-    UpLeft,
-    UpRight,
-    DownLeft,
-    DownRight,
+    Left = 0,
+    Right = 1,
+    Up = 2,
+    Down = 3,
+    UpLeft = 4,
+    UpRight = 5,
+    DownLeft = 6,
+    DownRight = 7,
 }
 
 impl Direction {
@@ -26,6 +28,30 @@ impl Direction {
         }
     }
 
+    pub fn num(&self) -> i32 {
+        *self as i32
+    }
+
+    pub fn is_diagonal(&self) -> bool {
+        let (dx, dy) = self.location();
+        dx != 0 && dy != 0
+    }
+
+    // The names used by the `#left`..`#down_right` constants
+    pub fn from_name(name: &str) -> Option<Direction> {
+        match name {
+            "left" => Some(Direction::Left),
+            "right" => Some(Direction::Right),
+            "up" => Some(Direction::Up),
+            "down" => Some(Direction::Down),
+            "up_left" => Some(Direction::UpLeft),
+            "up_right" => Some(Direction::UpRight),
+            "down_left" => Some(Direction::DownLeft),
+            "down_right" => Some(Direction::DownRight),
+            _ => None,
+        }
+    }
+
     pub fn from_num(input: i32) -> Option<Direction> {
         match input {
             0 => Some(Direction::Left),
@@ -1,94 +1,320 @@
+use std::collections::VecDeque;
+
 use quicksilver::{
     Future,
     Result,
+    Error,
+    load_file,
+    combinators::{join_all, result},
     lifecycle::{State, Window, Asset, Event},
     graphics::{Image, Color, Background::Img, View, Font, FontStyle, Mesh, Drawable, Background::Col},
     geom::{Rectangle, Vector, Shape, Transform},
     input::{Key, ButtonState}
 };
 
+use crate::game::Game;
 use crate::level::Level;
-use crate::spells::{SpellEngine, Spellbook, CasterRef};
+use crate::spells::Spellbook;
+use crate::events::GameEvent;
+use crate::utils::AbsoluteLocation;
+use crate::keymap::{Keymap, KeyChord, letter_index};
+use crate::messages::MessageCategory;
+use crate::save::{SaveFile, SavedLevel};
+use crate::editor::SpellEditor;
+use crate::animation::Animations;
+use crate::terrain_mesh::{TerrainMesh, tile_range};
+use crate::tileset::Tileset;
+use constants::MONSTER_SPELLBOOK;
 
 pub const SCREEN_SIZE: Vector = Vector {x: 800.0, y: 600.0};
+const HUD_MESSAGES: usize = 3;
+const HISTORY_LINES: usize = 25;
+const HUD_HEIGHT: f32 = 48.0;
+const BAR_SIZE: Vector = Vector {x: 200.0, y: 16.0};
+const EDITOR_LINES: usize = 24;
+const MAX_QUEUED: usize = 4; // turns waiting on animations, so held keys don't run far ahead
+const LEVEL_FILE: &'static str = "level.txt"; // F10 loads it again
+const TILESETS: [(&'static str, &'static str); 2] = [ // name and description file, F8 cycles through them
+    ("NetHack 3.6", "nethack_tiles.txt"),
+    ("Nevanda's", "nevanda_tiles.txt"),
+];
 
 struct SpriteSheet {
     image: Image,
+    tileset: Tileset,
     sprite_size: Vector,
-    rows: usize,
     columns: usize,
 }
 
 impl SpriteSheet {
-    fn get(&self, index: usize) -> Image {
+    fn load(description: &'static str) -> impl Future<Item = SpriteSheet, Error = Error> {
+        load_file(description).and_then(move |bytes| {
+            result(Tileset::parse(&String::from_utf8_lossy(&bytes)).map_err(|err| Error::ContextError(format!("{}: {}", description, err))))
+        }).and_then(|tileset| Image::load(tileset.image.clone()).map(move |image| {
+            let size = tileset.sprite_size;
+            SpriteSheet {
+                columns: (image.area().width() as u32 / size) as usize,
+                image: image,
+                tileset: tileset,
+                sprite_size: Vector::new(size, size),
+            }
+        }))
+    }
+
+    fn get(&self, sprite: &str) -> Image {
+        let index = self.tileset.index(sprite);
         self.image.subimage(Rectangle::new(Vector::new((index % self.columns) as u32, (index / self.columns) as u32).times(self.sprite_size), self.sprite_size))
     }
 }
 
 pub struct World {
     // TODO saved levels
-    assets: Asset<(SpriteSheet, Font)>,
+    assets: Asset<(Vec<SpriteSheet>, Font)>, // a sprite sheet for each of TILESETS
+    tileset: usize,
     mesh: Mesh,
-    message: Option<String>,
-    spell_engine: SpellEngine,
-    // TODO customizable player spellbook
-    spellbook: Spellbook,
+    terrain_mesh: Option<TerrainMesh>, // baked on the next draw when None
+    game: Game,
+    level_file: Option<Asset<::std::result::Result<Level, String>>>, // swapped into the game once it loads
+    history_offset: Option<usize>, // how far the history view is scrolled back, if it's open
+    blast: Vec<AbsoluteLocation>, // tiles hit by area spells last turn
+    animations: Animations,
+    queued: VecDeque<(String, Vec<i32>)>, // spell and arguments of each turn waiting to run
+    custom_spells: String,
+    editor: Option<SpellEditor>,
+    keymap: Asset<::std::result::Result<Keymap, String>>,
+    show_help: bool,
+    show_inventory: bool,
+    show_spellbook: bool,
 }
 
 impl State for World {
     fn new() -> Result<World> {
-        let image = Image::load("nethack3.6.1tiles32.png");
         Ok(World{
-            spell_engine: SpellEngine::new(Level::stupid()),
-            spellbook: Spellbook::monster_spellbook(),
-            message: None,
-            assets: Asset::new(image.map(|image| {
-                SpriteSheet {
-                    image: image,
-                    sprite_size: Vector::new(32, 32),
-                    rows: 37,
-                    columns: 40,
-                }
-            }).join(Font::load("font.ttf"))
+            // A lone player until the level file loads
+            game: Game::new(Level::parse("map\n@").expect("placeholder level should parse")),
+            level_file: Some(load_level_file()),
+            custom_spells: String::new(),
+            editor: None,
+            keymap: Asset::new(load_file("keymap.txt").map(move |bytes| {
+                Keymap::parse(&String::from_utf8_lossy(&bytes))
+            })),
+            show_help: false,
+            show_inventory: false,
+            show_spellbook: false,
+            history_offset: None,
+            blast: Vec::new(),
+            animations: Animations::new(),
+            queued: VecDeque::new(),
+            assets: Asset::new(join_all(TILESETS.iter().map(|(_, description)| SpriteSheet::load(description)))
+                .join(Font::load("font.ttf"))
             ),
+            tileset: 0,
             mesh: Mesh::new(),
+            terrain_mesh: None,
         })
     }
 
     fn draw(&mut self, window: &mut Window) -> Result<()> {
-        let message = &self.message;
-        let level = &self.spell_engine.level;
+        let mut help = None;
+        if self.show_help {
+            let spellbook = &self.game.player_spellbook;
+            let describe_spell = |spell: &str| match spellbook.cost(spell) {
+                Some(cost) => format!("{} ({})", spell, cost),
+                None => spell.to_string(),
+            };
+            self.keymap.execute(|keymap| {
+                help = Some(match keymap {
+                    Ok(keymap) => keymap.describe(&describe_spell),
+                    Err(err) => vec!(format!("Keymap error: {}", err)),
+                });
+                Ok(())
+            })?;
+        }
+        let log = &self.game.log;
+        let history_offset = self.history_offset;
+        let turn = self.game.spell_engine.turn;
+        let show_inventory = self.show_inventory;
+        let spellbook_screen = if self.show_spellbook { Some((&self.game.spell_memory, &self.game.player_spellbook)) } else { None };
+        let blast = &self.blast;
+        let editor = self.editor.as_ref();
+        let level = &self.game.spell_engine.level;
+        let animations = &self.animations;
+        let terrain_mesh = &mut self.terrain_mesh;
+        let tileset = self.tileset;
         let mut mesh = &mut self.mesh;
         mesh.clear();
-        self.assets.execute(|(sprite_sheet, font)| {
-            if let Some(msg) = message {
-                let style = FontStyle::new(24.0, Color::WHITE);
-                let image = font.render(&msg, &style)?;
-                Rectangle::new(Vector::new(0, 0), image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 20);
-            }
-
-            let camera = {
-                let (col, row) = level.monsters[0].location();
-                Transform::translate(
-                    (Vector::new(col as f32, row as f32).times(sprite_sheet.sprite_size) - (SCREEN_SIZE / 2)) * -1
-                )
-            };
+        self.assets.execute(|(sprite_sheets, font)| {
+            let sprite_sheet = &sprite_sheets[tileset];
+            for (i, msg) in log.recent(HUD_MESSAGES).iter().enumerate() {
+                let style = FontStyle::new(18.0, category_color(msg.category));
+                let image = font.render(&msg.text, &style)?;
+                Rectangle::new(Vector::new(0, 20 * i as u32), image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 20);
+            }
 
-            // TODO this can be rendered on its own mesh and only drawn when the level is loaded
-            for row in 0..20 {
-                for col in 0..20 {
-                    let tile_rect = Rectangle::new(Vector::new((32 * col) as u32, (32 * row) as u32), sprite_sheet.sprite_size);
-                    if let Some(sprite_index) = level.terrain[col][row].sprite_index{
-                        let tile_img = &sprite_sheet.get(sprite_index);
-                        tile_rect.draw(mesh, Img(tile_img), camera, 0);
+            // Status HUD along the bottom of the screen
+            {
+                let player = &level.monsters[0];
+                let top = SCREEN_SIZE.y - HUD_HEIGHT;
+                let style = FontStyle::new(16.0, Color::WHITE);
+                Rectangle::new(Vector::new(0.0, top), Vector::new(SCREEN_SIZE.x, HUD_HEIGHT)).draw(mesh, Col(Color::BLACK.with_alpha(0.7)), Transform::IDENTITY, 20);
+
+                let hp = (player.stats.current_hp, player.stats.max_hp);
+                let energy = (player.caster.energy, player.caster.max_energy());
+                for (i, (label, (current, max), color)) in [("HP", hp, Color::RED), ("EN", energy, Color::BLUE)].iter().enumerate() {
+                    let position = Vector::new(10.0, top + 6.0 + 20.0 * i as f32);
+                    draw_bar(mesh, Rectangle::new(position + Vector::new(40, 0), BAR_SIZE), *current, *max, *color, Transform::IDENTITY, 21);
+                    let image = font.render(&format!("{} {}/{}", label, current, max), &style)?;
+                    Rectangle::new(position, image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 22);
+                }
+
+                let effects = if player.effects.is_empty() {
+                    "none".to_string()
+                } else {
+                    player.effects.iter().map(|effect| format!("{} ({})", effect.condition.name(), effect.turns_left)).collect::<Vec<_>>().join(", ")
+                };
+                let status = format!("Depth {}   Turn {}   Effects: {}", level.depth, turn, effects);
+                let image = font.render(&status, &style)?;
+                Rectangle::new(Vector::new(260.0, top + 6.0), image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 22);
+            }
+
+            if show_inventory {
+                let inventory = &level.monsters[0].inventory;
+                Rectangle::new(Vector::new(0, 0), SCREEN_SIZE).draw(mesh, Col(Color::BLACK.with_alpha(0.8)), Transform::IDENTITY, 30);
+                let title = font.render("Inventory (letter to use, shift+letter to drop, F3 to close)", &FontStyle::new(20.0, Color::YELLOW))?;
+                Rectangle::new(Vector::new(10, 5), title.area().size()).draw(mesh, Img(&title), Transform::IDENTITY, 31);
+                if inventory.is_empty() {
+                    let image = font.render("You are not carrying anything", &FontStyle::new(16.0, Color::WHITE))?;
+                    Rectangle::new(Vector::new(10, 40), image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 31);
+                }
+                for (i, item) in inventory.iter().take(26).enumerate() {
+                    let top = 40 + 34 * i as u32;
+                    let item_img = &sprite_sheet.get(item.sprite);
+                    Rectangle::new(Vector::new(10, top), sprite_sheet.sprite_size).draw(mesh, Img(item_img), Transform::IDENTITY, 31);
+                    let equipped = if item.equipped { " (in use)" } else { "" };
+                    let label = format!("{} - {}{}", (b'a' + i as u8) as char, item.describe(), equipped);
+                    let image = font.render(&label, &FontStyle::new(16.0, Color::WHITE))?;
+                    Rectangle::new(Vector::new(50, top + 8), image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 31);
+                }
+            }
+
+            if let Some((memory, spellbook)) = spellbook_screen {
+                Rectangle::new(Vector::new(0, 0), SCREEN_SIZE).draw(mesh, Col(Color::BLACK.with_alpha(0.8)), Transform::IDENTITY, 30);
+                let title = font.render("Spellbook (letter to inscribe/erase, shift+letter to forget, F4 to close)", &FontStyle::new(20.0, Color::YELLOW))?;
+                Rectangle::new(Vector::new(10, 5), title.area().size()).draw(mesh, Img(&title), Transform::IDENTITY, 31);
+                for (i, spell) in memory.spells().iter().take(26).enumerate() {
+                    let color = if spell.inscribed { Color::WHITE } else { Color::WHITE.with_alpha(0.5) };
+                    let status = if spell.inscribed { "inscribed" } else { "not inscribed" };
+                    let cost = spellbook.cost(&spell.label).map(|cost| format!(", costs {}", cost)).unwrap_or_default();
+                    let label = format!("{} - {} ({}, from {}{})", (b'a' + i as u8) as char, spell.label, status, spell.source, cost);
+                    let image = font.render(&label, &FontStyle::new(16.0, color))?;
+                    Rectangle::new(Vector::new(10, 35 + 20 * i as u32), image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 31);
+                }
+            }
+
+            if let Some(offset) = history_offset {
+                Rectangle::new(Vector::new(0, 0), SCREEN_SIZE).draw(mesh, Col(Color::BLACK.with_alpha(0.8)), Transform::IDENTITY, 30);
+                let title = font.render("Message history (up/down to scroll, F2 to close)", &FontStyle::new(20.0, Color::YELLOW))?;
+                Rectangle::new(Vector::new(10, 5), title.area().size()).draw(mesh, Img(&title), Transform::IDENTITY, 31);
+                for (i, msg) in log.page(offset, HISTORY_LINES).iter().enumerate() {
+                    let style = FontStyle::new(16.0, category_color(msg.category));
+                    let image = font.render(&format!("[{}] {}", msg.turn, msg.text), &style)?;
+                    Rectangle::new(Vector::new(10, 35 + 22 * i as u32), image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 31);
+                }
+            }
+
+            if let Some(editor) = editor {
+                Rectangle::new(Vector::new(0, 0), SCREEN_SIZE).draw(mesh, Col(Color::BLACK.with_alpha(0.9)), Transform::IDENTITY, 30);
+                let title = match (editor.binding, editor.current_spell()) {
+                    (true, Some(label)) => format!("Press a key to bind {} to", label),
+                    _ => "Spell editor (ctrl+b to bind the spell under the cursor, F6 to close)".to_string(),
+                };
+                let title = font.render(&title, &FontStyle::new(20.0, Color::YELLOW))?;
+                Rectangle::new(Vector::new(10, 5), title.area().size()).draw(mesh, Img(&title), Transform::IDENTITY, 31);
+                let style = FontStyle::new(16.0, Color::WHITE);
+                let error_line = editor.error.as_ref().and_then(|(position, _)| position.map(|(line, _)| line));
+                // Scroll so the cursor stays on screen
+                let first = (editor.row + 1).saturating_sub(EDITOR_LINES);
+                for (i, line) in editor.lines.iter().enumerate().skip(first).take(EDITOR_LINES) {
+                    let position = Vector::new(10.0, 35.0 + 20.0 * (i - first) as f32);
+                    if error_line == Some(i) {
+                        Rectangle::new(position, Vector::new(SCREEN_SIZE.x - 20.0, 20.0)).draw(mesh, Col(Color::RED.with_alpha(0.4)), Transform::IDENTITY, 31);
+                    }
+                    if !line.trim().is_empty() {
+                        let image = font.render(line, &style)?;
+                        Rectangle::new(position, image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 32);
+                    }
+                    if i == editor.row {
+                        let prefix = &line[..editor.col];
+                        let offset = if prefix.is_empty() { 0.0 } else { font.render(prefix, &style)?.area().size().x };
+                        Rectangle::new(position + Vector::new(offset, 0.0), Vector::new(2.0, 18.0)).draw(mesh, Col(Color::YELLOW), Transform::IDENTITY, 33);
                     }
                 }
+                if let Some((position, message)) = &editor.error {
+                    let text = match position {
+                        Some((line, col)) => format!("Line {}, column {}: {}", line + 1, col + 1, message),
+                        None => message.clone(),
+                    };
+                    let image = font.render(&text, &FontStyle::new(16.0, Color::RED))?;
+                    Rectangle::new(Vector::new(10.0, SCREEN_SIZE.y - 30.0), image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 32);
+                }
+            }
+
+            if let Some(lines) = help {
+                Rectangle::new(Vector::new(0, 0), SCREEN_SIZE).draw(mesh, Col(Color::BLACK.with_alpha(0.8)), Transform::IDENTITY, 30);
+                let style = FontStyle::new(16.0, Color::WHITE);
+                // Lay the bindings out in columns that fit the screen height
+                let per_column = ((SCREEN_SIZE.y - 40.0) / 20.0) as usize;
+                let title = font.render("Key bindings (F1 to close)", &FontStyle::new(20.0, Color::YELLOW))?;
+                Rectangle::new(Vector::new(10, 5), title.area().size()).draw(mesh, Img(&title), Transform::IDENTITY, 31);
+                for (i, line) in lines.iter().enumerate() {
+                    let image = font.render(line, &style)?;
+                    let position = Vector::new(10.0 + 200.0 * (i / per_column) as f32, 35.0 + 20.0 * (i % per_column) as f32);
+                    Rectangle::new(position, image.area().size()).draw(mesh, Img(&image), Transform::IDENTITY, 31);
+                }
+            }
+
+            // The camera follows the player as they walk
+            let (col, row) = animations.position(&level.monsters[0].location());
+            let view = Rectangle::new(Vector::new(col, row).times(sprite_sheet.sprite_size) - (SCREEN_SIZE / 2), SCREEN_SIZE);
+            let camera = Transform::translate(view.pos * -1);
+            // Anything a tile or more off screen is skipped, so sprites part way in still show
+            let cols = tile_range(view.pos.x, view.size.x, sprite_sheet.sprite_size.x, level.terrain.len());
+            let rows = tile_range(view.pos.y, view.size.y, sprite_sheet.sprite_size.y, level.terrain[0].len());
+            let on_screen = |(col, row): &AbsoluteLocation| *col + 1 >= cols.0 && *col <= cols.1 && *row + 1 >= rows.0 && *row <= rows.1;
+
+            if terrain_mesh.is_none() {
+                *terrain_mesh = Some(TerrainMesh::bake(level, sprite_sheet.sprite_size, &|sprite| sprite_sheet.get(sprite)));
+            }
+            if let Some(terrain_mesh) = terrain_mesh.as_ref() {
+                terrain_mesh.draw(mesh, camera, &view);
+            }
+            for ((col, row), item) in level.items.iter().filter(|(location, _)| on_screen(location)) {
+                let item_rect = Rectangle::new(Vector::new(*col as u32, *row as u32).times(sprite_sheet.sprite_size), sprite_sheet.sprite_size);
+                let item_img = &sprite_sheet.get(item.sprite);
+                item_rect.draw(mesh, Img(item_img), camera, 2);
             }
-            for monster in level.monsters.iter() {
-                let (col, row) = monster.location();
-                let monster_rect = Rectangle::new(Vector::new((32 * col) as u32, (32 * row) as u32), sprite_sheet.sprite_size);
-                let monster_img = &sprite_sheet.get(monster.sprite_index);
+            for (col, row) in blast.iter().filter(|location| on_screen(location)) {
+                let blast_rect = Rectangle::new(Vector::new(*col as u32, *row as u32).times(sprite_sheet.sprite_size), sprite_sheet.sprite_size);
+                blast_rect.draw(mesh, Col(Color::RED.with_alpha(0.4)), camera, 5);
+            }
+            for monster in level.monsters.iter().filter(|monster| on_screen(&monster.location())) {
+                let (col, row) = animations.position(&monster.location());
+                let monster_rect = Rectangle::new(Vector::new(col, row).times(sprite_sheet.sprite_size), sprite_sheet.sprite_size);
+                let monster_img = &sprite_sheet.get(monster.sprite);
                 monster_rect.draw(mesh, Img(monster_img), camera, 10);
+                if monster.stats.current_hp < monster.stats.max_hp {
+                    let bar_rect = Rectangle::new(monster_rect.pos - Vector::new(0, 6), Vector::new(sprite_sheet.sprite_size.x, 4.0));
+                    draw_bar(mesh, bar_rect, monster.stats.current_hp, monster.stats.max_hp, Color::RED, camera, 11);
+                }
+            }
+            for ((col, row), sprite, alpha) in animations.fades() {
+                let rect = Rectangle::new(Vector::new(col as u32, row as u32).times(sprite_sheet.sprite_size), sprite_sheet.sprite_size);
+                draw_faded(mesh, &rect, &sprite_sheet.get(sprite), alpha, camera, 10);
+            }
+            for ((col, row), text, alpha) in animations.popups() {
+                let image = font.render(text, &FontStyle::new(18.0, Color::YELLOW))?;
+                let position = Vector::new(col, row).times(sprite_sheet.sprite_size) + (sprite_sheet.sprite_size - image.area().size()) / 2;
+                draw_faded(mesh, &Rectangle::new(position, image.area().size()), &image, alpha, camera, 12);
             }
             Ok(())
         })?;
@@ -97,32 +323,121 @@ impl State for World {
         Ok(())
     }
 
-    fn event(&mut self, event: &Event, _window: &mut Window) -> Result<()> {
+    // A queued turn runs once the last one has finished animating; a failed spell drops the rest
+    fn update(&mut self, _window: &mut Window) -> Result<()> {
+        if let Some(mut level_file) = self.level_file.take() {
+            let mut loaded = None;
+            level_file.execute(|level| {
+                loaded = Some(level.clone());
+                Ok(())
+            })?;
+            match loaded {
+                Some(Ok(level)) => {
+                    self.game.enter_level(level, LEVEL_FILE);
+                    self.reset_level_view();
+                },
+                Some(Err(err)) => self.game.log.push(self.game.spell_engine.turn, MessageCategory::System, err),
+                None => self.level_file = Some(level_file),
+            }
+        }
+        self.animations.tick();
+        if !self.animations.is_busy() {
+            if let Some((spell, args)) = self.queued.pop_front() {
+                if !self.run_turn(&spell, &args) {
+                    self.queued.clear();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, event: &Event, window: &mut Window) -> Result<()> {
         match event {
-            Event::Key(key, state) => match state {
-                ButtonState::Pressed => {
-                    let spell = match key {
-                        Key::Left => Some("left"),
-                        Key::Right => Some("right"),
-                        Key::Up => Some("up"),
-                        Key::Down => Some("down"),
-                        // TODO diagonals
-                        Key::Period => Some("wait"),
-
-                        Key::A => Some("attack_left"),
-                        Key::D => Some("attack_right"),
-                        Key::W => Some("attack_up"),
-                        Key::S => Some("attack_down"),
-                        // TODO diagonals
-                        // TODO
-                        _ => None,
-                    };
-                    if let Some(spell) = spell {
-                        self.message = self.spell_engine.do_turn(spell, &self.spellbook, &self.spellbook);
+            Event::Key(Key::F6, ButtonState::Pressed) => if self.editor.is_some() {
+                self.close_editor();
+            } else {
+                self.editor = Some(SpellEditor::new(&self.custom_spells));
+                self.compile_custom_spells();
+            },
+            Event::Key(key, ButtonState::Pressed) if self.editor.is_some() => self.edit_key(*key, window)?,
+            Event::Key(Key::F1, ButtonState::Pressed) => self.show_help = !self.show_help,
+            Event::Key(Key::Escape, ButtonState::Pressed) if self.show_help => self.show_help = false,
+            Event::Key(Key::F2, ButtonState::Pressed) => {
+                self.history_offset = match self.history_offset {
+                    Some(_) => None,
+                    None => Some(0),
+                }
+            },
+            Event::Key(key, ButtonState::Pressed) if self.history_offset.is_some() => {
+                let offset = self.history_offset.unwrap();
+                let max_offset = self.game.log.len().saturating_sub(HISTORY_LINES);
+                self.history_offset = match key {
+                    Key::Escape => None,
+                    Key::Up => Some((offset + 1).min(max_offset)),
+                    Key::Down => Some(offset.saturating_sub(1)),
+                    Key::PageUp => Some((offset + HISTORY_LINES).min(max_offset)),
+                    Key::PageDown => Some(offset.saturating_sub(HISTORY_LINES)),
+                    _ => Some(offset),
+                }
+            },
+            Event::Key(Key::F3, ButtonState::Pressed) => self.show_inventory = !self.show_inventory,
+            Event::Key(key, ButtonState::Pressed) if self.show_inventory => {
+                let keyboard = window.keyboard();
+                let shift = keyboard[Key::LShift].is_down() || keyboard[Key::RShift].is_down();
+                if *key == Key::Escape {
+                    self.show_inventory = false;
+                } else if let Some(slot) = letter_index(*key) {
+                    let spell = if shift { "drop" } else { "use_item" };
+                    self.queue_turns(&[spell.to_string()], &[slot as i32]);
+                }
+            },
+            Event::Key(Key::F4, ButtonState::Pressed) => self.show_spellbook = !self.show_spellbook,
+            Event::Key(key, ButtonState::Pressed) if self.show_spellbook => {
+                let keyboard = window.keyboard();
+                let shift = keyboard[Key::LShift].is_down() || keyboard[Key::RShift].is_down();
+                if *key == Key::Escape {
+                    self.show_spellbook = false;
+                } else if let Some(index) = letter_index(*key) {
+                    if shift {
+                        self.game.forget(index);
+                    } else {
+                        self.game.toggle_inscribed(index);
                     }
-                    ()
-                },
-                _ => (),
+                }
+            },
+            Event::Key(Key::F5, ButtonState::Pressed) => self.save(),
+            Event::Key(Key::F7, ButtonState::Pressed) => self.game.cycle_rollback(),
+            Event::Key(Key::F8, ButtonState::Pressed) => {
+                self.tileset = (self.tileset + 1) % TILESETS.len();
+                self.terrain_mesh = None;
+                let text = format!("Using {} tiles", TILESETS[self.tileset].0);
+                self.game.log.push(self.game.spell_engine.turn, MessageCategory::System, text);
+            },
+            Event::Key(Key::F9, ButtonState::Pressed) => self.load(),
+            Event::Key(Key::F10, ButtonState::Pressed) => self.level_file = Some(load_level_file()),
+            Event::Key(key, ButtonState::Pressed) => {
+                let keyboard = window.keyboard();
+                let chord = KeyChord {
+                    key: *key,
+                    shift: keyboard[Key::LShift].is_down() || keyboard[Key::RShift].is_down(),
+                    ctrl: keyboard[Key::LControl].is_down() || keyboard[Key::RControl].is_down(),
+                    alt: keyboard[Key::LAlt].is_down() || keyboard[Key::RAlt].is_down(),
+                };
+                let mut spells = None;
+                let mut keymap_error = None;
+                self.keymap.execute(|keymap| {
+                    match keymap {
+                        Ok(keymap) => spells = keymap.get(&chord).cloned(),
+                        Err(err) => keymap_error = Some(format!("Keymap error: {}", err)),
+                    }
+                    Ok(())
+                })?;
+                if let Some(err) = keymap_error {
+                    self.game.log.push(self.game.spell_engine.turn, MessageCategory::System, err);
+                }
+                if let Some(spells) = spells {
+                    self.queue_turns(&spells, &[]);
+                }
             },
             _ => (),
         };
@@ -130,5 +445,200 @@ impl State for World {
     }
 }
 
+impl World {
+    // Nothing drawn or queued for the last level carries over to a new one
+    fn reset_level_view(&mut self) {
+        self.terrain_mesh = None;
+        self.blast.clear();
+        self.queued.clear();
+        self.animations = Animations::new();
+    }
+
+    // Turns wait for the last turn's animations to finish
+    fn queue_turns(&mut self, spells: &[String], args: &[i32]) {
+        if self.queued.len() >= MAX_QUEUED {
+            return;
+        }
+        for spell in spells {
+            self.queued.push_back((spell.clone(), args.to_vec()));
+        }
+    }
+
+    // Returns false if the player's spell failed
+    fn run_turn(&mut self, spell: &str, args: &[i32]) -> bool {
+        self.blast.clear();
+        let sprites: Vec<(AbsoluteLocation, &'static str)> = self.game.spell_engine.level.monsters.iter()
+            .map(|monster| (monster.location(), monster.sprite))
+            .collect();
+        let (events, succeeded) = self.game.run_turn(spell, args);
+        self.animations.start(&events, &sprites);
+        for event in events {
+            match event {
+                GameEvent::Blast(area) => self.blast.extend(area),
+                GameEvent::TerrainChanged(_) => self.terrain_mesh = None,
+                _ => (),
+            }
+        }
+        succeeded
+    }
 
+    fn edit_key(&mut self, key: Key, window: &Window) -> Result<()> {
+        let keyboard = window.keyboard();
+        let chord = KeyChord {
+            key: key,
+            shift: keyboard[Key::LShift].is_down() || keyboard[Key::RShift].is_down(),
+            ctrl: keyboard[Key::LControl].is_down() || keyboard[Key::RControl].is_down(),
+            alt: keyboard[Key::LAlt].is_down() || keyboard[Key::RAlt].is_down(),
+        };
+        let binding = self.editor.as_ref().map(|editor| editor.binding).unwrap_or(false);
+        if binding {
+            let modifiers = [Key::LShift, Key::RShift, Key::LControl, Key::RControl, Key::LAlt, Key::RAlt];
+            if !modifiers.contains(&key) {
+                self.editor.as_mut().unwrap().binding = false;
+                if key != Key::Escape {
+                    self.bind_current_spell(chord)?;
+                }
+            }
+        } else if key == Key::Escape {
+            self.close_editor();
+        } else if chord.ctrl && key == Key::B {
+            self.editor.as_mut().unwrap().binding = true;
+        } else if self.editor.as_mut().unwrap().handle_key(key, chord.shift) {
+            self.custom_spells = self.editor.as_ref().unwrap().source();
+            self.compile_custom_spells();
+        }
+        Ok(())
+    }
+
+    fn bind_current_spell(&mut self, chord: KeyChord) -> Result<()> {
+        let turn = self.game.spell_engine.turn;
+        let label = match self.editor.as_ref().and_then(|editor| editor.current_spell()) {
+            Some(label) => label,
+            None => {
+                self.game.log.push(turn, MessageCategory::SpellError, "The cursor is not in a spell".to_string());
+                return Ok(())
+            },
+        };
+        if !self.game.player_spellbook.spell_names().contains(&label) {
+            let text = format!("{} has to compile before it can be bound", label);
+            self.game.log.push(turn, MessageCategory::SpellError, text);
+            return Ok(())
+        }
+        self.write_custom_spells();
+        let mut text = None;
+        self.keymap.execute(|keymap| {
+            text = Some(match keymap {
+                Ok(keymap) => match keymap.bind(chord, vec!(label.clone())) {
+                    Some(previous) => format!("Bound {} to {} (was {})", chord.describe(), label, previous.join(", ")),
+                    None => format!("Bound {} to {}", chord.describe(), label),
+                },
+                Err(err) => format!("Keymap error: {}", err),
+            });
+            Ok(())
+        })?;
+        if let Some(text) = text {
+            self.game.log.push(turn, MessageCategory::System, text);
+        }
+        Ok(())
+    }
 
+    fn close_editor(&mut self) {
+        self.editor = None;
+        self.write_custom_spells();
+    }
+
+    // The player's spells are compiled after the monster spellbook so they can call into it
+    fn compile_custom_spells(&mut self) {
+        let source = format!("{}\n{}", MONSTER_SPELLBOOK, self.custom_spells);
+        let library_lines = MONSTER_SPELLBOOK.matches('\n').count() + 1;
+        let error = match Spellbook::compile(&source) {
+            Ok(spellbook) => {
+                self.game.player_spellbook = spellbook;
+                None
+            },
+            Err(err) => {
+                let position = err.line_col(&source)
+                    .and_then(|(line, col)| if line >= library_lines { Some((line - library_lines, col)) } else { None });
+                Some((position, err.message))
+            },
+        };
+        if let Some(editor) = self.editor.as_mut() {
+            editor.error = error;
+        }
+    }
+
+    // Puts newly written spells in the spell memory, inscribed
+    fn write_custom_spells(&mut self) {
+        let turn = self.game.spell_engine.turn;
+        let library = self.game.monster_spellbook.spell_names();
+        for label in self.game.player_spellbook.spell_names() {
+            if !library.contains(&label) && self.game.spell_memory.write(&label) {
+                self.game.log.push(turn, MessageCategory::System, format!("You write {} into your spellbook", label));
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        let turn = self.game.spell_engine.turn;
+        let save_file = SaveFile {
+            turn: turn,
+            messages: self.game.log.clone(),
+            spells: self.game.spell_memory.clone(),
+            custom_spells: self.custom_spells.clone(),
+            level: SavedLevel::new(&self.game.spell_engine.level),
+        };
+        match save_file.save() {
+            Ok(()) => self.game.log.push(turn, MessageCategory::System, "Game saved".to_string()),
+            Err(err) => self.game.log.push(turn, MessageCategory::System, err),
+        }
+    }
+
+    fn load(&mut self) {
+        match SaveFile::load().and_then(|save_file| save_file.level.restore().map(|level| (save_file, level))) {
+            Ok((save_file, level)) => {
+                self.game.spell_engine.level = level;
+                self.reset_level_view();
+                self.game.spell_engine.turn = save_file.turn;
+                self.game.log = save_file.messages;
+                self.game.spell_memory = save_file.spells;
+                self.custom_spells = save_file.custom_spells;
+                self.editor = None;
+                self.compile_custom_spells();
+                self.game.log.push(save_file.turn, MessageCategory::System, "Game loaded".to_string());
+            },
+            Err(err) => self.game.log.push(self.game.spell_engine.turn, MessageCategory::System, err),
+        }
+    }
+}
+
+// A filled bar showing `current` out of `max` on a dark background
+fn draw_bar(mesh: &mut Mesh, area: Rectangle, current: u32, max: u32, color: Color, transform: Transform, z: i32) {
+    let fraction = if max == 0 { 0.0 } else { current.min(max) as f32 / max as f32 };
+    area.draw(mesh, Col(Color::BLACK.with_alpha(0.6)), transform, z);
+    let filled = Rectangle::new(area.pos, Vector::new(area.size.x * fraction, area.size.y));
+    filled.draw(mesh, Col(color), transform, z + 1);
+}
+
+// Images can't be drawn see-through directly, so the vertices are tinted afterwards
+fn draw_faded(mesh: &mut Mesh, area: &Rectangle, image: &Image, alpha: f32, transform: Transform, z: i32) {
+    let first = mesh.vertices.len();
+    area.draw(mesh, Img(image), transform, z);
+    for vertex in mesh.vertices[first..].iter_mut() {
+        vertex.col = Color::WHITE.with_alpha(alpha);
+    }
+}
+
+fn category_color(category: MessageCategory) -> Color {
+    match category {
+        MessageCategory::Combat => Color::ORANGE,
+        MessageCategory::Item => Color::GREEN,
+        MessageCategory::SpellError => Color::RED,
+        MessageCategory::System => Color::WHITE,
+    }
+}
+
+fn load_level_file() -> Asset<::std::result::Result<Level, String>> {
+    Asset::new(load_file(LEVEL_FILE).map(|bytes| {
+        Level::parse(&String::from_utf8_lossy(&bytes)).map_err(|err| format!("{}: {}", LEVEL_FILE, err))
+    }))
+}